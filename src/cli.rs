@@ -1,3 +1,14 @@
+use crate::{
+    cmd::{
+        alphabet::{Alphabet, SeqType},
+        faidx::OnError,
+        kmer::KmerSort,
+        orf::{Nested, StartCodon},
+        rmdup::DupKey,
+    },
+    report::ReportFormat,
+    utils::CompressionFormat,
+};
 use clap::{
     ArgAction, Parser,
     builder::{
@@ -6,7 +17,6 @@ use clap::{
     },
     value_parser,
 };
-use std::path::PathBuf;
 
 const STYLES: Styles = Styles::styled()
     .header(AnsiColor::Green.on_default().effects(Effects::BOLD))
@@ -29,13 +39,22 @@ const STYLES: Styles = Styles::styled()
     before_help = r"Fakit supports reading and writing gzip (.gz) format.
 Bzip2 (.bz2) and xz (.xz) format is supported since v0.3.0.
 Under the same compression level, xz has the highest compression ratio but consumes more time.
+Compressed input is detected by magic number, both from file and stdin.
+Output file ending in .bgz is bgzf compressed. With --threads > 1, gzip output is written
+as bgzf blocks, and bgzf/xz/zstd output is compressed with multiple threads.
+Input can also be a http(s):// or s3:// url, s3 url is resolved with AWS_ENDPOINT_URL (eg. MinIO),
+AWS_REGION and signed with AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY if set. faidx reads remote
+fasta by http range requests, the .fai (and .gzi) file must be next to it.
 
 Compression level:
   format   range   default   crate
   gzip     1-9     6         https://crates.io/crates/flate2
   bzip2    1-9     6         https://crates.io/crates/bzip2
   xz       1-9     6         https://crates.io/crates/xz2
-  zstd     1-4     2         roughly equals to zstd 1, 3, 7, 11, respectively",
+  bgzf     1-9     6         https://crates.io/crates/noodles-bgzf
+  zstd     1-22    6         https://crates.io/crates/zstd
+
+Exit status: 0 success, 1 other failure, 2 invalid argument, 3 invalid input data, 4 io failure",
     help_template = "{name}: {about}\n\nVersion: {version}\
     \n\nAuthors: {author} <mmtinfo@163.com>\
     \nSource code: https://github.com/sharkLoc/fakit.git\
@@ -52,13 +71,32 @@ pub struct Args {
     )]
     pub width: usize,
 
-    /// set gzip/bzip2/xz/zstd compression level 1 (compress faster) - 9 (compress better) for output file, just work with option -o/--out,
-    /// {n}file name ending in .gz/.bz2/.xz/.zst will be compressed automatically
-    #[arg(long = "compress-level", default_value_t = 6, global = true, value_parser = value_parser!(u32).range(1..=9), value_name = "int",
+    /// set compression level for output, gzip/bgzf/bzip2/xz: 1 (compress faster) - 9 (compress better), zstd: 1 - 22,
+    /// {n}file name ending in .gz/.bgz/.bz2/.xz/.zst will be compressed automatically
+    #[arg(long = "compress-level", default_value_t = 6, global = true, value_parser = value_parser!(u32).range(1..=22), value_name = "int",
         help_heading = Some("Global Arguments")
     )]
     pub compression_level: u32,
 
+    /// set output compression format for both file and stdout, override the format from file name extension
+    #[arg(long = "output-compression", value_enum, global = true, value_name = "str",
+        help_heading = Some("Global Arguments")
+    )]
+    pub output_compression: Option<CompressionFormat>,
+
+    /// number of worker threads for record processing and compressed output, output order is kept the same as input
+    #[arg(short = 'j', long = "threads", default_value_t = 1, global = true, value_name = "int",
+        help_heading = Some("Global Arguments")
+    )]
+    pub threads: usize,
+
+    /// output format of report commands: summ, size, search, kmer, kmer-query, flatten, window, validate,
+    /// {n}amplicon --report and rmdup --groups, default tsv, flatten and window write their plain layout if not given
+    #[arg(long = "format", value_enum, global = true, value_name = "str",
+        help_heading = Some("Global Arguments")
+    )]
+    pub format: Option<ReportFormat>,
+
     /// if file name specified, write log message to this file, or write to stderr
     #[arg(long = "log", global = true, help_heading = Some("Global Arguments"), value_name = "str")]
     pub logfile: Option<String>,
@@ -69,6 +107,22 @@ pub struct Args {
     )]
     pub verbose: u8,
 
+    /// no header line in tsv and csv output of summ, size, flatten, window, validate, amplicon and rmdup
+    #[arg(long = "no-header", global = true, conflicts_with = "header", help_heading = Some("Global FLAGS"))]
+    pub no_header: bool,
+
+    /// add header line to tsv and csv output of search, kmer and kmer-query, they have no header by default
+    #[arg(long = "header", global = true, help_heading = Some("Global FLAGS"))]
+    pub header: bool,
+
+    /// tag each record with its source file name as sequence id prefix, eg. >a.fa.gz|seq1
+    #[arg(long = "tag-source", global = true, help_heading = Some("Global FLAGS"))]
+    pub tag_source: bool,
+
+    /// check each line while reading, empty ids, sequence before the first header and invalid characters fail with file, line and record number
+    #[arg(long = "strict", global = true, help_heading = Some("Global FLAGS"))]
+    pub strict: bool,
+
     /// be quiet and do not show extra information
     #[arg(short = 'q', long = "quiet", global= true, help_heading = Some("Global FLAGS"))]
     pub quiet: bool,
@@ -89,22 +143,22 @@ pub enum Subcli {
     /// get first N records from fasta file
     #[command(visible_alias = "head")]
     topn {
-        /// input fasta file, or read from stdin
-        input: Option<PathBuf>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// print first N fasta records
         #[arg(short = 'n', long = "num", default_value_t = 10, value_name = "int")]
         num: usize,
         /// output fasta file name, or write to stdout
         #[arg(short = 'o', long = "out", value_name = "str")]
-        output: Option<PathBuf>,
+        output: Option<String>,
     },
     /// get last N records from fasta file
     #[command(
         before_help = "note: the command need to read file twice in 2-pass mode, do not use in stream"
     )]
     tail {
-        /// input fasta file
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// print last N fasta records
         #[arg(short = 'n', long = "num", default_value_t = 10, value_name = "int")]
         num: usize,
@@ -117,8 +171,8 @@ pub enum Subcli {
     },
     /// convert fasta to fastq file
     fa2fq {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// fasta to fastq and generate fake fastq quality.
         #[arg(short = 'Q', long = "qual", default_value_t = 'F', value_name = "char")]
         qual: char,
@@ -132,12 +186,37 @@ pub enum Subcli {
     /// create index and random access to fasta files
     #[command(visible_alias = "fai")]
     faidx {
-        /// input uncompressed fasta file
+        /// input uncompressed or bgzip compressed fasta file or url, .gzi index is created for bgzip file
         input: Option<String>,
         /// fasta region format and start is 1-based, eg. chr1:1-5000 chr2:100-800
         /// usage:  fakit faidx seq.fa chr1:1-5000 chr2:100-800 ...
+        /// whole sequence name and open-ended range are allowed, eg. chr1 chr1:100- chr1:-500
         #[arg(verbatim_doc_comment)]
         region: Option<Vec<String>>,
+        /// read regions from bed file, or a region list file with columns: region, [name], [strand]
+        /// sequence on minus strand is reverse complemented
+        #[arg(
+            short = 'r',
+            long = "region-file",
+            verbatim_doc_comment,
+            value_name = "str"
+        )]
+        region_file: Option<String>,
+        /// set output sequence name, placeholders: {chrom} {start} {end} {strand} {name} {region}
+        /// {name} is the name column in region file, e.g. -n "{name}::{region}({strand})"
+        #[arg(
+            short = 'n',
+            long = "name-format",
+            verbatim_doc_comment,
+            value_name = "str"
+        )]
+        name_format: Option<String>,
+        /// if specified, rebuild the index when it is older than fasta file or not match the file size
+        #[arg(short = 'R', long = "rebuild", help_heading = Some("FLAGS"))]
+        rebuild: bool,
+        /// how to deal with a region that can't be parsed or extracted
+        #[arg(short = 'e', long = "on-error", value_enum, default_value_t = OnError::Skip, value_name = "str")]
+        on_error: OnError,
         /// output fasta file name, or write to stdout
         #[arg(short = 'o', long = "out", value_name = "str")]
         output: Option<String>,
@@ -145,12 +224,12 @@ pub enum Subcli {
     /// flatten fasta sequences
     #[command(visible_alias = "flat")]
     flatten {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// if specified, keep sequence id description
         #[arg(short = 'k', long = "keep", help_heading = Some("FLAGS"))]
         keep: bool,
-        /// output seprater, can be ",",  ";",
+        /// output seprater of plain layout, can be ",",  ";", not used with --format
         #[arg(short = 's', long = "sep", default_value_t = '\t', value_name = "CHAR")]
         sep: char,
         /// if specified, add N base count in output
@@ -162,6 +241,9 @@ pub enum Subcli {
         /// if specified, add GC content(%) in output
         #[arg(short = 'g', long = "gc-content", help_heading = Some("FLAGS"))]
         gc: bool,
+        /// sequence type, gc content is written as NA for protein
+        #[arg(long = "alphabet", value_enum, default_value_t = SeqType::Auto, value_name = "str")]
+        alphabet: SeqType,
         /// output file name or write to stdout
         #[arg(short = 'o', long = "out", value_name = "str")]
         output: Option<String>,
@@ -169,8 +251,8 @@ pub enum Subcli {
     /// print fasta records in a range
     #[command(visible_alias = "rg")]
     range {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// skip first int fasta records
         #[arg(short = 's', long = "skip", default_value_t = 0, value_name = "int")]
         skip: usize,
@@ -184,8 +266,8 @@ pub enum Subcli {
     /// rename sequence id in fasta file
     #[command(visible_alias = "rn")]
     rename {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// if specified, keep sequence id description
         #[arg(short = 'k', long = "keep", help_heading = Some("FLAGS"))]
         keep: bool,
@@ -199,20 +281,30 @@ pub enum Subcli {
     /// get a reverse-complement of fasta file.
     #[command(visible_alias = "rev")]
     reverse {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// if set, just output reverse sequences
         #[arg(short = 'r', long = "reverse", help_heading = Some("FLAGS"))]
         rev: bool,
+        /// if set, just output complement sequences
+        #[arg(short = 'c', long = "complement", help_heading = Some("FLAGS"))]
+        comp: bool,
+        /// if set, complement A to U for rna sequences
+        #[arg(short = 'R', long = "rna", help_heading = Some("FLAGS"))]
+        rna: bool,
+        /// append a tag to sequence id, "/rc", "/rev" or "/comp" by mode if no value given, eg. --tag=_minus
+        #[arg(short = 't', long = "tag", num_args = 0..=1, require_equals = true,
+            default_missing_value = "", value_name = "str")]
+        tag: Option<String>,
         /// output file name or write to stdout
         #[arg(short = 'o', long = "out", value_name = "str")]
         out: Option<String>,
     },
-    /// stat dna fasta gc content by sliding windows
+    /// stat fasta gc content by sliding windows
     #[command(visible_alias = "slide")]
     window {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// set sliding window size
         #[arg(
             short = 'W',
@@ -229,18 +321,21 @@ pub enum Subcli {
             value_name = "int"
         )]
         step: usize,
-        /// if specified, keep fasta format in output result
+        /// if specified, keep fasta format in output result, not allowed with --format
         #[arg(short = 'k', long = "keep", help_heading = Some("FLAGS"))]
         keep: bool,
+        /// sequence type, gc content is not reported for protein
+        #[arg(long = "alphabet", value_enum, default_value_t = SeqType::Auto, value_name = "str")]
+        alphabet: SeqType,
         /// output result file name, or write to stdout
-        ///header format: seqid    start   end gc_rate sequence
+        ///plain layout: seqid start-end:gc_rate sequence
         #[arg(short = 'o', long = "out", verbatim_doc_comment, value_name = "str")]
         output: Option<String>,
     },
-    /// grep fasta sequences by name/seq
+    /// grep fasta sequences by name/description/seq or id list
     grep {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// specify regex pattern/motif, e.g., -p "ATC{2,}" or -p ATCCG, search multiple pattern/motif, -p "ATCCG|GCTAA"
         /// when searching by sequence name, the sequence prefix ">" is not included in the header.
         #[arg(
@@ -249,24 +344,67 @@ pub enum Subcli {
             verbatim_doc_comment,
             value_name = "str"
         )]
-        pat: String,
+        pat: Option<String>,
+        /// pattern to grep sequences by, if it differs from the name pattern of -p, implies -s
+        #[arg(short = 'P', long = "seq-pattern", value_name = "str")]
+        seq_pattern: Option<String>,
         /// grep sequences by full name
         #[arg(short = 'n', long = "by-name", help_heading = Some("FLAGS"))]
         name: bool,
+        /// grep sequences by description, the header after sequence id
+        #[arg(short = 'd', long = "by-desc", help_heading = Some("FLAGS"))]
+        desc: bool,
         /// grep sequences by sequence
         #[arg(short = 's', long = "by-seq", help_heading = Some("FLAGS"))]
         seq: bool,
+        /// with more than one of name, description and sequence, a record must match all of them instead of any
+        #[arg(short = 'A', long = "and", help_heading = Some("FLAGS"))]
+        and: bool,
+        /// treat sequence pattern as IUPAC motifs separated by "|" and search both strands, eg. -P "GANTC|GATC",
+        /// {n}name and description are still matched as regex
+        #[arg(short = 'D', long = "degenerate", help_heading = Some("FLAGS"))]
+        degenerate: bool,
         /// ignore case insensitive
         #[arg(short = 'i', long = "ignore-case", help_heading = Some("FLAGS"))]
         ignore: bool,
+        /// output records that do not match, long form only as -v is the global --verbosity
+        #[arg(long = "invert-match", visible_alias = "invert", help_heading = Some("FLAGS"))]
+        invert: bool,
+        /// only print the number of matched records
+        #[arg(short = 'c', long = "count", conflicts_with = "list_order", help_heading = Some("FLAGS"))]
+        count: bool,
+        /// file of sequence ids to grep by exact match, one per line, the first word of each line is used,
+        /// {n}not usable with --tag-source as the file name prefix never matches a listed id
+        #[arg(
+            short = 'f',
+            long = "id-file",
+            value_name = "str",
+            conflicts_with_all = ["pat", "seq_pattern"]
+        )]
+        id_file: Option<String>,
+        /// match ids in list file against the full header, whole line of list file is used
+        #[arg(long = "full-header", requires = "id_file", help_heading = Some("FLAGS"))]
+        full_header: bool,
+        /// output records in list file order, sequences are read by index if the input is a single indexed fasta,
+        /// {n}or matched records are kept in memory
+        #[arg(short = 'l', long = "list-order", requires = "id_file", conflicts_with = "invert", help_heading = Some("FLAGS"))]
+        list_order: bool,
+        /// write ids in list file but not found in input to this plain text file
+        #[arg(
+            short = 'u',
+            long = "unmatched",
+            requires = "id_file",
+            value_name = "str"
+        )]
+        unmatched: Option<String>,
         /// output search result file name, or write to stdout
         #[arg(short = 'o', long = "out", value_name = "str")]
         output: Option<String>,
     },
     /// convert all bases to lower/upper case, filter by length
     seq {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// convert all bases to lowercase
         #[arg(short = 'l', long = "lower-case", help_heading = Some("FLAGS"))]
         lower: bool,
@@ -289,6 +427,9 @@ pub enum Subcli {
         /// if specified, fasta sequences gc content more than length required will be discarded
         #[arg(short = 'G', long = "gc-max", value_name = "float")]
         gc_max: Option<f64>,
+        /// sequence type, gc content filter is not allowed for protein
+        #[arg(long = "alphabet", value_enum, default_value_t = SeqType::Auto, value_name = "str")]
+        alphabet: SeqType,
         /// output file name or write to stdout
         #[arg(short = 'o', long = "out", value_name = "str")]
         out: Option<String>,
@@ -296,8 +437,8 @@ pub enum Subcli {
     /// sort fasta file by name/seq/gc/length
     #[command(before_help = "note: all records will be readed into memory")]
     sort {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// sort sequences by name
         #[arg(short = 'n', long = "sort-by-name" ,help_heading = Some("FLAGS"))]
         name: bool,
@@ -317,12 +458,37 @@ pub enum Subcli {
         #[arg(short = 'o', long = "out", value_name = "str")]
         out: Option<String>,
     },
+    /// remove duplicate records by name, id or sequence
+    rmdup {
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
+        /// key to find duplicates by, the first record of each key is kept
+        #[arg(short = 'k', long = "key", value_enum, default_value_t = DupKey::Seq, value_name = "str")]
+        key: DupKey,
+        /// a sequence and its reverse complement are duplicates, only works with -k seq
+        #[arg(short = 'C', long = "canonical", help_heading = Some("FLAGS"))]
+        canonical: bool,
+        /// keep 128-bit digests of keys instead of full keys to save memory for large sequences,
+        /// {n}collisions are very unlikely but possible
+        #[arg(short = 'd', long = "digest", help_heading = Some("FLAGS"))]
+        digest: bool,
+        /// write removed duplicate records to this file
+        #[arg(short = 'r', long = "removed", value_name = "str")]
+        removed: Option<String>,
+        /// write duplicate groups to this file, columns: kept, duplicates, removed
+        #[arg(short = 'g', long = "groups", value_name = "str")]
+        groups: Option<String>,
+        /// output file name or write to stdout
+        #[arg(short = 'o', long = "out", value_name = "str")]
+        out: Option<String>,
+    },
     /// search subsequences/motifs from fasta file
     search {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// specify uppercase pattern/motif, e.g., -p "ATC{2,}" or -p ATCCG
         ///search multiple pattern/motif, -p "ATCCG|GCTAA"
+        ///regex hits have 1-based start and end
         #[arg(
             short = 'p',
             long = "pattern",
@@ -336,17 +502,90 @@ pub enum Subcli {
         /// ignore case insensitive
         #[arg(short = 'i', long = "ignore-case", help_heading = Some("FLAGS"))]
         ignore_case: bool,
-        /// if specified, show header in result
-        #[arg(short = 'H', long = "header", help_heading = Some("FLAGS"))]
-        Header: bool,
+        /// treat pattern as IUPAC nucleotide motifs separated by "|", eg. -p "GANTC|GATC",
+        /// search both strands with overlapping hits and output bed6 with matched sequence,
+        /// 0-based start as in bed, header line starts with "#" in tsv
+        #[arg(short = 'm', long = "motif", help_heading = Some("FLAGS"))]
+        motif: bool,
+        /// only search the forward strand in motif mode
+        #[arg(short = 'f', long = "forward", requires = "motif", help_heading = Some("FLAGS"))]
+        forward: bool,
+        /// max mismatches allowed in motif mode, or max edits with --edit, less than motif length
+        #[arg(
+            short = 'n',
+            long = "mismatch",
+            default_value_t = 0,
+            requires = "motif",
+            value_name = "int"
+        )]
+        mismatch: usize,
+        /// count insertions and deletions as well as mismatches in motif mode
+        #[arg(short = 'e', long = "edit", requires = "motif", help_heading = Some("FLAGS"))]
+        edit: bool,
         /// output search result file name, or write to stdout
         #[arg(short = 'o', long = "out", value_name = "str")]
         output: Option<String>,
     },
-    /// a simple kmer counter
+    /// in-silico PCR, find amplicons of primer pairs allowing mismatches and IUPAC bases
+    #[command(
+        before_help = "note: report of amplicon coordinates is bed6 followed by product length and primer mismatches"
+    )]
+    amplicon {
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
+        /// forward primer sequence 5'-3', IUPAC codes allowed
+        #[arg(
+            short = 'F',
+            long = "forward",
+            value_name = "str",
+            requires = "reverse"
+        )]
+        forward: Option<String>,
+        /// reverse primer sequence 5'-3', IUPAC codes allowed
+        #[arg(
+            short = 'R',
+            long = "reverse",
+            value_name = "str",
+            requires = "forward"
+        )]
+        reverse: Option<String>,
+        /// tsv file of primer pairs: name, forward primer and reverse primer, lines starting with # are skipped
+        #[arg(short = 'p', long = "primers", value_name = "str", conflicts_with_all = ["forward", "reverse"])]
+        primers: Option<String>,
+        /// max mismatches allowed in each primer
+        #[arg(
+            short = 'n',
+            long = "mismatch",
+            default_value_t = 0,
+            value_name = "int"
+        )]
+        mismatch: usize,
+        /// min product length including primers
+        #[arg(short = 'm', long = "min-len", default_value_t = 1, value_name = "int")]
+        min_len: usize,
+        /// max product length including primers
+        #[arg(
+            short = 'M',
+            long = "max-len",
+            default_value_t = 5000,
+            value_name = "int"
+        )]
+        max_len: usize,
+        /// if specified, output amplicon sequences without primers
+        #[arg(short = 't', long = "trim-primers", help_heading = Some("FLAGS"))]
+        trim_primers: bool,
+        /// write amplicon coordinates to this file, bed6 with total primer mismatches as score,
+        /// followed by pair name, length and mismatches of each primer
+        #[arg(short = 'r', long = "report", value_name = "str")]
+        report: Option<String>,
+        /// output amplicon fasta file name, or write to stdout
+        #[arg(short = 'o', long = "out", value_name = "str")]
+        output: Option<String>,
+    },
+    /// count kmers with 2-bit encoding, canonical counting and frequency histogram
     kmer {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// set kmer size
         #[arg(
             short = 'k',
@@ -355,9 +594,64 @@ pub enum Subcli {
             value_name = "int"
         )]
         size: usize,
-        /// add header info in output file
-        #[arg(short = 'H', long, help_heading = Some("FLAGS"))]
-        header: bool,
+        /// count canonical kmers, the smaller one of a kmer and its reverse complement
+        #[arg(short = 'C', long = "canonical", help_heading = Some("FLAGS"))]
+        canonical: bool,
+        /// only output kmers seen at least int times
+        #[arg(
+            short = 'm',
+            long = "min-count",
+            default_value_t = 1,
+            value_name = "int"
+        )]
+        min_count: u64,
+        /// only output kmers seen at most int times
+        #[arg(short = 'M', long = "max-count", value_name = "int")]
+        max_count: Option<u64>,
+        /// sort output kmers by kmer sequence or count
+        #[arg(short = 's', long = "sort", value_enum, default_value_t = KmerSort::Kmer, value_name = "str")]
+        sort: KmerSort,
+        /// output kmer frequency histogram instead of kmers: frequency and number of distinct kmers
+        #[arg(long = "hist", help_heading = Some("FLAGS"))]
+        hist: bool,
+        /// save kmer counts to a binary database for kmer-query instead of text output, eg. db.kdb
+        #[arg(
+            short = 'd',
+            long = "dump",
+            value_name = "str",
+            conflicts_with = "hist"
+        )]
+        dump: Option<String>,
+        /// output file name or write to stdout
+        #[arg(short = 'o', long = "out", value_name = "str")]
+        out: Option<String>,
+    },
+    /// query a kmer database created by kmer --dump
+    #[command(
+        name = "kmer-query",
+        before_help = "note: without a query option, all kmers of the database are written as text"
+    )]
+    kmer_query {
+        /// kmer database file, eg. db.kdb
+        db: String,
+        /// fasta files to report kmer coverage of each sequence against the database
+        #[arg(conflicts_with_all = ["list", "intersect", "subtract", "union"])]
+        input: Vec<String>,
+        /// file of kmers to look up, one per line
+        #[arg(short = 'l', long = "list", value_name = "str")]
+        list: Option<String>,
+        /// output kmers in both databases, with the smaller count
+        #[arg(long = "intersect", value_name = "str", conflicts_with_all = ["list", "subtract", "union"])]
+        intersect: Option<String>,
+        /// output kmers not in the other database
+        #[arg(long = "subtract", value_name = "str", conflicts_with_all = ["list", "union"])]
+        subtract: Option<String>,
+        /// output kmers in any database, with counts summed
+        #[arg(long = "union", value_name = "str", conflicts_with = "list")]
+        union: Option<String>,
+        /// save the set operation result to a database instead of text output
+        #[arg(short = 'd', long = "dump", value_name = "str")]
+        dump: Option<String>,
         /// output file name or write to stdout
         #[arg(short = 'o', long = "out", value_name = "str")]
         out: Option<String>,
@@ -366,8 +660,8 @@ pub enum Subcli {
     #[command(visible_alias = "shuf")]
     #[command(before_help = "note: all records will be readed into memory")]
     shuffle {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// set rand seed.
         #[arg(short = 's', long = "seed", default_value_t = 69, value_name = "int")]
         seed: u64,
@@ -377,17 +671,17 @@ pub enum Subcli {
     },
     /// report fasta sequence base count
     size {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// if specified, show more information
         #[arg(short='a', long="all", help_heading=Some("FLAGS"))]
         all: bool,
+        /// sequence type, protein gets molecular weight, pI and residue composition with --all
+        #[arg(long = "alphabet", value_enum, default_value_t = SeqType::Auto, value_name = "str")]
+        alphabet: SeqType,
         /// if specified, keep sequence id description
         #[arg(short = 'k', long = "keep", help_heading = Some("FLAGS"))]
         keep: bool,
-        /// no header in output file
-        #[arg(short = 'n', long = "no-header", help_heading = Some("FLAGS"))]
-        noheader: bool,
         /// output file name or write to stdout
         #[arg(short = 'o', long = "out", value_name = "str")]
         output: Option<String>,
@@ -395,8 +689,8 @@ pub enum Subcli {
     /// subsample sequences from big fasta file
     #[command(visible_alias = "sample")]
     subfa {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// set rand seed
         #[arg(short = 's', long = "seed", default_value_t = 69, value_name = "int")]
         seed: u64,
@@ -413,8 +707,8 @@ pub enum Subcli {
     /// split fasta file by sequence id
     #[command(before_help = "note: each sequence results in a separate file")]
     split {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// set output file extension, eg. fa, fa.gz, fna.xz, fna.bz2
         #[arg(short = 'e', long = "ext", value_name = "str")]
         ext: String,
@@ -427,8 +721,8 @@ pub enum Subcli {
     },
     /// split fasta file by sequence number
     split2 {
-        /// input fasta file, or read from stdin
-        input: Option<String>,
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
         /// set record number for each mini fasta file
         #[arg(short = 'n', long = "num", default_value_t = 100, value_name = "int")]
         num: usize,
@@ -445,7 +739,7 @@ pub enum Subcli {
         #[arg(short = 'p', long = "prefix", default_value_t = String::from("sub"), value_name = "str")]
         name: String,
     },
-    /// simple summary for dna fasta files
+    /// simple summary for dna, rna or protein fasta files
     #[command(visible_alias = "stat")]
     summ {
         /// files to process, eg. *.fasta
@@ -456,14 +750,108 @@ pub enum Subcli {
         /// if specified, show more information
         #[arg(short='a', long="all", help_heading=Some("FLAGS"))]
         all: bool,
+        /// sequence type, protein gets residue composition with --all, auto detected from the first file
+        #[arg(long = "alphabet", value_enum, default_value_t = SeqType::Auto, value_name = "str")]
+        alphabet: SeqType,
+        /// genome size for NG50/NG90 and LG50/LG90, eg. 3100000000
+        #[arg(short = 'g', long = "genome-size", value_name = "int")]
+        genome_size: Option<usize>,
+        /// if specified, add sequence length histogram columns
+        #[arg(short = 'H', long = "hist", help_heading = Some("FLAGS"))]
+        hist: bool,
+        /// if specified, add a summary row of all files combined
+        #[arg(short = 't', long = "tabular", help_heading = Some("FLAGS"))]
+        tabular: bool,
         /// output summary file name, or write to stdout
         #[arg(short = 'o', long = "out", value_name = "str")]
         output: Option<String>,
     },
+    /// check fasta files and write a report of malformed records
+    #[command(
+        before_help = "note: checks alphabet, empty id and sequence, duplicate id and line width, exit with status 3 if any issue found"
+    )]
+    validate {
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
+        /// allowed sequence alphabet, case insensitive
+        #[arg(short = 'a', long = "alphabet", value_enum, default_value_t = Alphabet::Iupac, value_name = "str")]
+        alphabet: Alphabet,
+        /// output report file name, or write to stdout
+        #[arg(short = 'o', long = "out", value_name = "str")]
+        output: Option<String>,
+    },
+    /// translate dna or rna sequences to protein
+    translate {
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
+        /// NCBI translation table id, eg. 1: standard, 2: vertebrate mitochondrial, 11: bacterial,
+        /// {n}see all tables: https://www.ncbi.nlm.nih.gov/Taxonomy/Utils/wprintgc.cgi
+        #[arg(short = 't', long = "table", default_value_t = 1, value_name = "int")]
+        table: u8,
+        /// frames to translate, 1, 2, 3, -1, -2, -3 and 6 for all six frames, eg. -f 1,-1
+        #[arg(
+            short = 'f',
+            long = "frame",
+            default_value = "1",
+            value_delimiter = ',',
+            allow_negative_numbers = true,
+            value_name = "int"
+        )]
+        frame: Vec<i8>,
+        /// if specified, stop translation at the first stop codon, the stop is not written
+        #[arg(short = 's', long = "trim-stop", help_heading = Some("FLAGS"))]
+        trim_stop: bool,
+        /// if specified, translate the first codon as M if it is an initiation codon of the table
+        #[arg(short = 'M', long = "init-start", help_heading = Some("FLAGS"))]
+        init_start: bool,
+        /// output fasta file name, or write to stdout
+        #[arg(short = 'o', long = "out", value_name = "str")]
+        output: Option<String>,
+    },
+    /// find open reading frames in all six frames
+    orf {
+        /// input fasta files with mixed compression allowed, "-" or no input for stdin
+        input: Vec<String>,
+        /// NCBI translation table id
+        #[arg(short = 't', long = "table", default_value_t = 1, value_name = "int")]
+        table: u8,
+        /// minimum orf length in nucleotides, stop codon included
+        #[arg(
+            short = 'm',
+            long = "min-len",
+            default_value_t = 75,
+            value_name = "int"
+        )]
+        min_len: usize,
+        /// codons an orf can start with
+        #[arg(short = 's', long = "start", value_enum, default_value_t = StartCodon::Atg, value_name = "str")]
+        start: StartCodon,
+        /// how to report nested orfs sharing the same stop codon
+        #[arg(short = 'n', long = "nested", value_enum, default_value_t = Nested::Longest, value_name = "str")]
+        nested: Nested,
+        /// if specified, also report orfs without stop codon at the sequence end
+        #[arg(short = 'p', long = "partial", help_heading = Some("FLAGS"))]
+        partial: bool,
+        /// if specified, output protein sequences instead of nucleotide sequences
+        #[arg(short = 'P', long = "protein", help_heading = Some("FLAGS"))]
+        protein: bool,
+        /// write orf coordinates to this bed file
+        #[arg(short = 'b', long = "bed", value_name = "str")]
+        bed: Option<String>,
+        /// write orf coordinates to this gff3 file
+        #[arg(short = 'g', long = "gff", value_name = "str")]
+        gff: Option<String>,
+        /// output fasta file name, or write to stdout
+        #[arg(short = 'o', long = "out", value_name = "str")]
+        output: Option<String>,
+    },
     /// show codon table and amino acid name
     codon {
-        /// amino acid short name eg. S
+        /// amino acid short name eg. S, or * for stop codons
         #[arg(short = 'n', long = "name", value_name = "str")]
         name: Option<String>,
+        /// NCBI translation table id
+        #[arg(short = 't', long = "table", default_value_t = 1, value_name = "int")]
+        table: u8,
     },
 }
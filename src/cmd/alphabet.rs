@@ -0,0 +1,263 @@
+use crate::errors::FakitError;
+use clap::ValueEnum;
+use log::info;
+use std::io::{self, BufRead, BufReader, Read};
+
+// sequence bytes sampled from the input head to guess the sequence type
+const DETECT_SAMPLE_SIZE: usize = 10_000;
+// input read at most to guess the sequence type, in chunks, a long line is not read in whole
+const DETECT_PREFIX_SIZE: usize = 1 << 20;
+const DETECT_CHUNK_SIZE: u64 = 1 << 16;
+
+/// the 20 standard amino acids, in the column order of composition output
+pub const AMINO_ACIDS: &[u8; 20] = b"ACDEFGHIKLMNPQRSTVWY";
+
+/// allowed sequence letters, case insensitive, gap '-' is always allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Alphabet {
+    /// A C G T N
+    Dna,
+    /// A C G U N
+    Rna,
+    /// dna and rna with IUPAC ambiguity codes
+    Iupac,
+    /// 20 amino acids, B Z J U O X and stop codon '*'
+    Protein,
+}
+
+impl Alphabet {
+    pub fn letters(&self) -> &'static [u8] {
+        match self {
+            Alphabet::Dna => b"ACGTN",
+            Alphabet::Rna => b"ACGUN",
+            Alphabet::Iupac => b"ACGTURYSWKMBDHVN",
+            Alphabet::Protein => b"ACDEFGHIKLMNPQRSTVWYBZJUOX*",
+        }
+    }
+}
+
+/// sequence type of the input, used by statistics commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SeqType {
+    /// guess from the sequences at the beginning of input
+    Auto,
+    Dna,
+    Rna,
+    Protein,
+}
+
+impl SeqType {
+    pub fn is_protein(&self) -> bool {
+        *self == SeqType::Protein
+    }
+}
+
+/// Resolve SeqType::Auto by the sequences at the beginning of input, the sampled bytes are
+/// put back, so the returned reader still starts from the first byte.
+pub fn detect_seq_type(
+    mut reader: Box<dyn BufRead + Send>,
+    seq_type: SeqType,
+) -> Result<(SeqType, Box<dyn BufRead + Send>), FakitError> {
+    if seq_type != SeqType::Auto {
+        return Ok((seq_type, reader));
+    }
+
+    let mut head = vec![];
+    let mut counts = [0usize; 256];
+    let mut sampled = 0usize;
+    // header lines are skipped, a header may be split by chunks
+    let (mut line_start, mut in_header) = (true, false);
+    while sampled < DETECT_SAMPLE_SIZE && head.len() < DETECT_PREFIX_SIZE {
+        let start = head.len();
+        if (&mut reader)
+            .take(DETECT_CHUNK_SIZE)
+            .read_to_end(&mut head)?
+            == 0
+        {
+            break;
+        }
+        for &b in &head[start..] {
+            if line_start && b == b'>' {
+                in_header = true;
+            }
+            line_start = b == b'\n';
+            if line_start {
+                in_header = false;
+            } else if !in_header && b.is_ascii_alphabetic() {
+                counts[b.to_ascii_uppercase() as usize] += 1;
+                sampled += 1;
+            }
+        }
+    }
+
+    let count = |x: &[u8]| x.iter().map(|&b| counts[b as usize]).sum::<usize>();
+    let nucleotide = count(b"ACGTUN");
+    let seq_type = if sampled == 0 || nucleotide as f64 >= sampled as f64 * 0.9 {
+        if counts[b'U' as usize] > 0 && counts[b'T' as usize] == 0 {
+            SeqType::Rna
+        } else {
+            SeqType::Dna
+        }
+    } else {
+        SeqType::Protein
+    };
+    info!("detected sequence type: {:?}", seq_type);
+
+    let reader = BufReader::new(io::Cursor::new(head).chain(reader));
+    Ok((seq_type, Box::new(reader)))
+}
+
+/// Count residues in AMINO_ACIDS order, the last one is for any other residue,
+/// gap '-' and stop '*' are not counted.
+pub fn residue_counts(seq: &[u8]) -> [usize; 21] {
+    let mut counts = [0usize; 21];
+    for b in seq {
+        match b {
+            b'-' | b'*' => {}
+            _ => match AMINO_ACIDS
+                .iter()
+                .position(|x| *x == b.to_ascii_uppercase())
+            {
+                Some(i) => counts[i] += 1,
+                None => counts[20] += 1,
+            },
+        }
+    }
+    counts
+}
+
+// average residue mass in dalton, X and other ambiguous residues use 110
+fn residue_mass(aa: u8) -> f64 {
+    match aa.to_ascii_uppercase() {
+        b'A' => 71.0788,
+        b'R' => 156.1875,
+        b'N' => 114.1038,
+        b'D' => 115.0886,
+        b'C' => 103.1388,
+        b'E' => 129.1155,
+        b'Q' => 128.1307,
+        b'G' => 57.0519,
+        b'H' => 137.1411,
+        b'I' | b'L' => 113.1594,
+        b'K' => 128.1741,
+        b'M' => 131.1926,
+        b'F' => 147.1766,
+        b'P' => 97.1167,
+        b'S' => 87.0782,
+        b'T' => 101.1051,
+        b'W' => 186.2132,
+        b'Y' => 163.1760,
+        b'V' => 99.1326,
+        _ => 110.0,
+    }
+}
+
+/// Average molecular weight of a protein sequence in dalton.
+pub fn molecular_weight(seq: &[u8]) -> f64 {
+    let residues = seq
+        .iter()
+        .filter(|b| !matches!(b, b'-' | b'*'))
+        .map(|&b| residue_mass(b))
+        .sum::<f64>();
+    if residues == 0.0 {
+        0.0
+    } else {
+        residues + 18.01524
+    }
+}
+
+// net charge at the given pH, with EMBOSS pKa values
+fn net_charge(counts: &[usize; 21], ph: f64) -> f64 {
+    let count = |aa: u8| {
+        let i = AMINO_ACIDS.iter().position(|x| *x == aa).unwrap_or(20);
+        counts[i] as f64
+    };
+    let positive = |pka: f64| 1.0 / (1.0 + 10f64.powf(ph - pka));
+    let negative = |pka: f64| -1.0 / (1.0 + 10f64.powf(pka - ph));
+
+    positive(8.6)
+        + count(b'K') * positive(10.8)
+        + count(b'R') * positive(12.5)
+        + count(b'H') * positive(6.5)
+        + negative(3.6)
+        + count(b'D') * negative(3.9)
+        + count(b'E') * negative(4.1)
+        + count(b'C') * negative(8.5)
+        + count(b'Y') * negative(10.1)
+}
+
+/// Isoelectric point of a protein sequence, the pH where the net charge is zero.
+pub fn isoelectric_point(seq: &[u8]) -> f64 {
+    let counts = residue_counts(seq);
+    let (mut low, mut high) = (0.0, 14.0);
+    while high - low > 0.001 {
+        let mid = (low + high) / 2.0;
+        if net_charge(&counts, mid) > 0.0 {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(data: &[u8]) -> (SeqType, Vec<u8>) {
+        let reader: Box<dyn BufRead + Send> = Box::new(io::Cursor::new(data.to_vec()));
+        let (seq_type, mut reader) = detect_seq_type(reader, SeqType::Auto).unwrap();
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).unwrap();
+        (seq_type, buf)
+    }
+
+    #[test]
+    fn detect_sequence_type() {
+        assert_eq!(detect(b">s1 MKLV\nACGTNacgt\n").0, SeqType::Dna);
+        assert_eq!(detect(b">s1\nACGUUGCA\n").0, SeqType::Rna);
+        assert_eq!(detect(b">s1\nMKLVFFEQRW\n").0, SeqType::Protein);
+        assert_eq!(detect(b"").0, SeqType::Dna);
+    }
+
+    #[test]
+    fn detect_single_line_sequence() {
+        // a sequence on one long line, only a prefix is read and the input is kept whole
+        let mut data = b">chr1 EEEEEEEE\n".to_vec();
+        data.extend(b"ACGT".repeat(1 << 20));
+        data.extend(b"\n>p1\nMKLVFFEQRW\n");
+        let (seq_type, buf) = detect(&data);
+        assert_eq!(seq_type, SeqType::Dna);
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn protein_weight() {
+        assert!((molecular_weight(b"G") - 75.067).abs() < 0.001);
+        assert!((molecular_weight(b"ACDEFGHIKLMNPQRSTVWY") - 2395.736).abs() < 0.001);
+        // gap and stop are not residues
+        assert_eq!(molecular_weight(b"G-*"), molecular_weight(b"g"));
+        assert_eq!(molecular_weight(b""), 0.0);
+    }
+
+    #[test]
+    fn protein_isoelectric_point() {
+        // charges of both termini cancel half way between their pKa
+        assert!((isoelectric_point(b"G") - 6.1).abs() < 0.01);
+        assert!(isoelectric_point(b"KKKKRRRR") > 11.0);
+        assert!(isoelectric_point(b"DDDDEEEE") < 4.0);
+        let pi = isoelectric_point(b"MKLVFFEQRW");
+        assert!(pi > 6.1 && pi < 10.8);
+    }
+
+    #[test]
+    fn count_residues() {
+        let counts = residue_counts(b"AAcx-*B");
+        assert_eq!(counts[0], 2);
+        assert_eq!(counts[1], 1);
+        // x and B are counted as other residues, gap and stop are not counted
+        assert_eq!(counts[20], 2);
+        assert_eq!(counts.iter().sum::<usize>(), 5);
+    }
+}
@@ -0,0 +1,323 @@
+use crate::{
+    cmd::{reverse::reverse_complement, search::Motif, wrap::write_record},
+    errors::FakitError,
+    report::{Cell, ReportFormat, ReportWriter},
+    utils::{CompressionFormat, batch_records, file_reader, file_writer, multi_reader},
+};
+use log::info;
+use paraseq::{
+    fasta::{Reader, RecordSet},
+    fastx::Record,
+};
+use std::{
+    io::{BufRead, Write},
+    path::Path,
+};
+
+struct PrimerPair {
+    name: String,
+    forward: Motif,
+    reverse: Motif,
+}
+
+struct Amplicon {
+    // 0-based half-open position of the product on the forward strand
+    start: usize,
+    end: usize,
+    // product without primers
+    inner_start: usize,
+    inner_end: usize,
+    strand: char,
+    forward_diff: usize,
+    reverse_diff: usize,
+}
+
+// primer pairs from a tsv file: name, forward primer, reverse primer
+fn read_primers<P: AsRef<Path> + Copy>(path: P) -> Result<Vec<PrimerPair>, FakitError> {
+    let mut pairs = vec![];
+    for (n, line) in file_reader(Some(path))?.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split('\t').map(|x| x.trim()).collect::<Vec<&str>>();
+        if fields.len() < 3 {
+            return Err(FakitError::InvalidArgument(format!(
+                "primer file {} line {}: expect name, forward and reverse primer separated by tab",
+                path.as_ref().display(),
+                n + 1
+            )));
+        }
+        pairs.push(PrimerPair {
+            name: fields[0].to_string(),
+            forward: Motif::new(fields[1])?,
+            reverse: Motif::new(fields[2])?,
+        });
+    }
+    Ok(pairs)
+}
+
+// products of a primer pair on both strands: the forward primer binds the minus strand and reads
+// on the plus strand, the reverse primer is found as its reverse complement downstream
+fn find_amplicons(
+    seq: &[u8],
+    pair: &PrimerPair,
+    max_diff: usize,
+    min_len: usize,
+    max_len: usize,
+) -> Vec<Amplicon> {
+    let mut amplicons = vec![];
+    for (left, right, strand) in [
+        (&pair.forward, &pair.reverse, '+'),
+        (&pair.reverse, &pair.forward, '-'),
+    ] {
+        let lefts = left.find_strand(seq, max_diff, false, false);
+        if lefts.is_empty() {
+            continue;
+        }
+        let rights = right.find_strand(seq, max_diff, false, true);
+        for l in lefts.iter() {
+            for r in rights
+                .iter()
+                .filter(|r| r.end >= l.end && r.start >= l.start)
+            {
+                let len = r.end - l.start;
+                if len < min_len || len > max_len {
+                    continue;
+                }
+                let (forward_diff, reverse_diff) = match strand {
+                    '+' => (l.diff, r.diff),
+                    _ => (r.diff, l.diff),
+                };
+                amplicons.push(Amplicon {
+                    start: l.start,
+                    end: r.end,
+                    inner_start: l.end,
+                    inner_end: r.start.max(l.end),
+                    strand,
+                    forward_diff,
+                    reverse_diff,
+                });
+            }
+        }
+    }
+    amplicons.sort_by_key(|x| (x.start, x.end));
+    amplicons
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn amplicon_fasta<P: AsRef<Path>>(
+    input: &[P],
+    tag_source: bool,
+    strict: bool,
+    forward: Option<&str>,
+    reverse: Option<&str>,
+    primers: Option<&P>,
+    max_diff: usize,
+    min_len: usize,
+    max_len: usize,
+    trim_primers: bool,
+    output: Option<&P>,
+    report: Option<&P>,
+    header: bool,
+    format: ReportFormat,
+    line_width: usize,
+    compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
+) -> Result<(), FakitError> {
+    let pairs = match (forward, reverse, primers) {
+        (_, _, Some(path)) => read_primers(path)?,
+        (Some(forward), Some(reverse), None) => vec![PrimerPair {
+            name: "amplicon".to_string(),
+            forward: Motif::new(forward)?,
+            reverse: Motif::new(reverse)?,
+        }],
+        _ => {
+            return Err(FakitError::InvalidArgument(
+                "a forward and reverse primer, or a primer file is required".to_string(),
+            ));
+        }
+    };
+    if min_len > max_len {
+        return Err(FakitError::InvalidArgument(format!(
+            "min product length {} is larger than max length {}",
+            min_len, max_len
+        )));
+    }
+    info!(
+        "primer pairs: {}, max mismatches per primer: {}",
+        pairs.len(),
+        max_diff
+    );
+
+    let mut fa_reader = multi_reader(input, tag_source, strict).map(Reader::new)?;
+    let mut rset = RecordSet::default();
+    let mut writer = file_writer(output, compression_level, threads, output_compression)?;
+    let mut report_writer = report
+        .map(|path| {
+            file_writer(Some(path), compression_level, threads, output_compression)
+                .map(|w| ReportWriter::new(w, format))
+        })
+        .transpose()?;
+    // bed6 with total primer mismatches as score, followed by pair name, product length and
+    // mismatches of each primer
+    let columns = [
+        "chrom",
+        "chromStart",
+        "chromEnd",
+        "name",
+        "score",
+        "strand",
+        "pair",
+        "length",
+        "forward_mismatch",
+        "reverse_mismatch",
+    ];
+    if header {
+        if let Some(w) = report_writer.as_mut() {
+            // a bed header line is a comment
+            if format == ReportFormat::Tsv {
+                w.write_all(b"#")?;
+            }
+            format.write_header(w, &columns)?;
+        }
+    }
+
+    let mut counts = vec![0usize; pairs.len()];
+    let mut nrec = 0usize;
+    while rset.fill(&mut fa_reader)? {
+        for rec in batch_records(&rset, &mut nrec) {
+            let rec = rec?;
+            let seq = rec.seq();
+            let name = rec.id_str().split_whitespace().next().unwrap_or("");
+            for (pair, count) in pairs.iter().zip(counts.iter_mut()) {
+                for amp in find_amplicons(&seq, pair, max_diff, min_len, max_len) {
+                    *count += 1;
+                    let amp_id = format!("{}_amp{}", pair.name, count);
+                    let region = if trim_primers {
+                        &seq[amp.inner_start..amp.inner_end]
+                    } else {
+                        &seq[amp.start..amp.end]
+                    };
+                    let product = match amp.strand {
+                        '+' => region.to_vec(),
+                        _ => reverse_complement(region),
+                    };
+                    let id = format!(
+                        "{} {}:{}-{}({}) len={}",
+                        amp_id,
+                        name,
+                        amp.start + 1,
+                        amp.end,
+                        amp.strand,
+                        amp.end - amp.start
+                    );
+                    write_record(&mut writer, id.as_bytes(), &product, line_width)?;
+
+                    if let Some(w) = report_writer.as_mut() {
+                        let values = [
+                            Cell::from(name),
+                            Cell::from(amp.start),
+                            Cell::from(amp.end),
+                            Cell::from(amp_id.as_str()),
+                            Cell::from(amp.forward_diff + amp.reverse_diff),
+                            Cell::Str(amp.strand.to_string()),
+                            Cell::from(pair.name.as_str()),
+                            Cell::from(amp.end - amp.start),
+                            Cell::from(amp.forward_diff),
+                            Cell::from(amp.reverse_diff),
+                        ];
+                        let row = columns.iter().zip(values).collect::<Vec<_>>();
+                        format.write_row(w, &row)?;
+                    }
+                }
+            }
+        }
+    }
+    writer.flush()?;
+    if let Some(w) = report_writer {
+        w.finish()?;
+    }
+
+    for (pair, count) in pairs.iter().zip(counts) {
+        info!("primer pair {}: {} amplicons", pair.name, count);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORWARD: &str = "ACGGATCCAG";
+    const REVERSE: &str = "CTGAGCTCAA";
+
+    fn pair() -> PrimerPair {
+        PrimerPair {
+            name: "p1".to_string(),
+            forward: Motif::new(FORWARD).unwrap(),
+            reverse: Motif::new(REVERSE).unwrap(),
+        }
+    }
+
+    // a 30 bp product at 4..34 on the plus strand
+    fn template() -> Vec<u8> {
+        let reverse = reverse_complement(REVERSE.as_bytes());
+        [
+            b"TTTT",
+            FORWARD.as_bytes(),
+            b"CCCCCAAAAA",
+            &reverse,
+            b"TTTT",
+        ]
+        .concat()
+    }
+
+    fn products(seq: &[u8], max_diff: usize, min_len: usize, max_len: usize) -> Vec<String> {
+        find_amplicons(seq, &pair(), max_diff, min_len, max_len)
+            .into_iter()
+            .map(|x| {
+                format!(
+                    "{}-{}{} {}-{} {}/{}",
+                    x.start,
+                    x.end,
+                    x.strand,
+                    x.inner_start,
+                    x.inner_end,
+                    x.forward_diff,
+                    x.reverse_diff
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn amplicons_on_both_strands() {
+        let seq = template();
+        assert_eq!(products(&seq, 0, 1, 1000), ["4-34+ 14-24 0/0"]);
+        let minus = reverse_complement(&seq);
+        assert_eq!(products(&minus, 0, 1, 1000), ["4-34- 14-24 0/0"]);
+        // no product without the reverse primer
+        assert!(products(&seq[..30], 0, 1, 1000).is_empty());
+    }
+
+    #[test]
+    fn primer_mismatches() {
+        let mut seq = template();
+        seq[6] = b'T';
+        assert!(products(&seq, 0, 1, 1000).is_empty());
+        assert_eq!(products(&seq, 1, 1, 1000), ["4-34+ 14-24 1/0"]);
+        // a product on the minus strand counts the mismatch for the forward primer too
+        let minus = reverse_complement(&seq);
+        assert_eq!(products(&minus, 1, 1, 1000), ["4-34- 14-24 1/0"]);
+    }
+
+    #[test]
+    fn product_size_limits() {
+        let seq = template();
+        assert_eq!(products(&seq, 0, 30, 30).len(), 1);
+        assert!(products(&seq, 0, 31, 1000).is_empty());
+        assert!(products(&seq, 0, 1, 29).is_empty());
+    }
+}
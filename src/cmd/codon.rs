@@ -1,123 +1,394 @@
 use colored::*;
-use log::error;
-use std::collections::HashMap;
 
 use crate::errors::FakitError;
 
-pub fn show_codon(name: Option<String>) -> Result<(), FakitError> {
-    let codons = vec![
-        "UUU (Phe/F)",
-        "UUC (Phe/F)",
-        "UUA (Leu/L)",
-        "UUG (Leu/L)",
-        "UCU (Ser/S)",
-        "UCC (Ser/S)",
-        "UCA (Ser/S)",
-        "UCG (Ser/S)",
-        "UAU (Tyr/Y)",
-        "UAC (Tyr/Y)",
-        "UAA (Stop)",
-        "UAG (Stop)",
-        "UGU (Cys/C)",
-        "UGC (Cys/C)",
-        "UGA (Stop)",
-        "UGG (Trp/W)",
-        "CUU (Leu/L)",
-        "CUC (Leu/L)",
-        "CUA (Leu/L)",
-        "CUG (Leu/L)",
-        "CCU (Pro/P)",
-        "CCC (Pro/P)",
-        "CCA (Pro/P)",
-        "CCG (Pro/P)",
-        "CAU (His/H)",
-        "CAC (His/H)",
-        "CAA (Gln/Q)",
-        "CAG (Gln/Q)",
-        "CGU (Arg/R)",
-        "CGC (Arg/R)",
-        "CGA (Arg/R)",
-        "CGG (Arg/R)",
-        "AUU (Ile/I)",
-        "AUC (Ile/I)",
-        "AUA (Ile/I)",
-        "AUG (Start)",
-        "ACU (Thr/T)",
-        "ACC (Thr/T)",
-        "ACA (Thr/T)",
-        "ACG (Thr/T)",
-        "AAU (Asn/N)",
-        "AAC (Asn/N)",
-        "AAA (Lys/K)",
-        "AAG (Lys/K)",
-        "AGU (Ser/S)",
-        "AGC (Ser/S)",
-        "AGA (Arg/R)",
-        "AGG (Arg/R)",
-        "GUU (Val/V)",
-        "GUC (Val/V)",
-        "GUA (Val/V)",
-        "GUG (Val/V)",
-        "GCU (Ala/A)",
-        "GCC (Ala/A)",
-        "GCA (Ala/A)",
-        "GCG (Ala/A)",
-        "GAU (Asp/D)",
-        "GAC (Asp/D)",
-        "GAA (Glu/E)",
-        "GAG (Glu/E)",
-        "GGU (Gly/G)",
-        "GGC (Gly/G)",
-        "GGA (Gly/G)",
-        "GGG (Gly/G)",
-    ];
-    let aa: HashMap<String, String> = vec![
-        ("M".to_string(), "AUG".to_string()),
-        ("W".to_string(), "UGG".to_string()),
-        ("N".to_string(), "AAU,AAC".to_string()),
-        ("D".to_string(), "GAU,GAC".to_string()),
-        ("C".to_string(), "UGU,UGC".to_string()),
-        ("E".to_string(), "GAA,GAG".to_string()),
-        ("Q".to_string(), "CAA,CAG".to_string()),
-        ("H".to_string(), "CAU,CAC".to_string()),
-        ("K".to_string(), "AAA,AAG".to_string()),
-        ("F".to_string(), "UUU,UUC".to_string()),
-        ("Y".to_string(), "UAU,UAC".to_string()),
-        ("I".to_string(), "AUU,AUC,AUA".to_string()),
-        ("A".to_string(), "GCU,GCC,GCA,GCG".to_string()),
-        ("G".to_string(), "GGU,GGC,GGA,GGG".to_string()),
-        ("P".to_string(), "CCU,CCC,CCA,CCG".to_string()),
-        ("T".to_string(), "ACU,ACC,ACA,ACG".to_string()),
-        ("V".to_string(), "GUU,GUC,GUA,GUG".to_string()),
-        ("R".to_string(), "CGU,CGC,CGA,CGG,AGA,AGG".to_string()),
-        ("S".to_string(), "UCU,UCC,UCA,UCG,AGU,AGC".to_string()),
-        ("L".to_string(), "UUA,UUG,CUU,CUC,CUA,CUG".to_string()),
-    ]
-    .into_iter()
-    .collect();
+// codon order of NCBI translation tables: first, second and third base in T C A G order
+const BASES: &[u8; 4] = b"TCAG";
+
+/// A NCBI genetic code, amino acids and start codons are listed in the NCBI codon order,
+/// see https://www.ncbi.nlm.nih.gov/Taxonomy/Utils/wprintgc.cgi
+pub struct CodonTable {
+    pub id: u8,
+    pub name: &'static str,
+    aas: &'static [u8; 64],
+    starts: &'static [u8; 64],
+}
+
+const CODON_TABLES: &[CodonTable] = &[
+    CodonTable {
+        id: 1,
+        name: "Standard",
+        aas: b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"---M---------------M---------------M----------------------------",
+    },
+    CodonTable {
+        id: 2,
+        name: "Vertebrate Mitochondrial",
+        aas: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG",
+        starts: b"--------------------------------MMMM---------------M------------",
+    },
+    CodonTable {
+        id: 3,
+        name: "Yeast Mitochondrial",
+        aas: b"FFLLSSSSYY**CCWWTTTTPPPPHHQQRRRRIIMMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"----------------------------------MM---------------M------------",
+    },
+    CodonTable {
+        id: 4,
+        name: "Mold, Protozoan, and Coelenterate Mitochondrial and Mycoplasma/Spiroplasma",
+        aas: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"--MM---------------M------------MMMM---------------M------------",
+    },
+    CodonTable {
+        id: 5,
+        name: "Invertebrate Mitochondrial",
+        aas: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSSSVVVVAAAADDEEGGGG",
+        starts: b"---M----------------------------MMMM---------------M------------",
+    },
+    CodonTable {
+        id: 6,
+        name: "Ciliate, Dasycladacean and Hexamita Nuclear",
+        aas: b"FFLLSSSSYYQQCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    CodonTable {
+        id: 9,
+        name: "Echinoderm and Flatworm Mitochondrial",
+        aas: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M---------------M------------",
+    },
+    CodonTable {
+        id: 10,
+        name: "Euplotid Nuclear",
+        aas: b"FFLLSSSSYY**CCCWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    CodonTable {
+        id: 11,
+        name: "Bacterial, Archaeal and Plant Plastid",
+        aas: b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"---M---------------M------------MMMM---------------M------------",
+    },
+    CodonTable {
+        id: 12,
+        name: "Alternative Yeast Nuclear",
+        aas: b"FFLLSSSSYY**CC*WLLLSPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-------------------M---------------M----------------------------",
+    },
+    CodonTable {
+        id: 13,
+        name: "Ascidian Mitochondrial",
+        aas: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSGGVVVVAAAADDEEGGGG",
+        starts: b"---M------------------------------MM---------------M------------",
+    },
+    CodonTable {
+        id: 14,
+        name: "Alternative Flatworm Mitochondrial",
+        aas: b"FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    CodonTable {
+        id: 15,
+        name: "Blepharisma Nuclear",
+        aas: b"FFLLSSSSYY*QCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    CodonTable {
+        id: 16,
+        name: "Chlorophycean Mitochondrial",
+        aas: b"FFLLSSSSYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    CodonTable {
+        id: 21,
+        name: "Trematode Mitochondrial",
+        aas: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M---------------M------------",
+    },
+    CodonTable {
+        id: 22,
+        name: "Scenedesmus obliquus Mitochondrial",
+        aas: b"FFLLSS*SYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    CodonTable {
+        id: 23,
+        name: "Thraustochytrium Mitochondrial",
+        aas: b"FF*LSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"--------------------------------M--M---------------M------------",
+    },
+    CodonTable {
+        id: 24,
+        name: "Rhabdopleuridae Mitochondrial",
+        aas: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+        starts: b"---M---------------M---------------M---------------M------------",
+    },
+    CodonTable {
+        id: 25,
+        name: "Candidate Division SR1 and Gracilibacteria",
+        aas: b"FFLLSSSSYY**CCGWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"---M-------------------------------M---------------M------------",
+    },
+    CodonTable {
+        id: 26,
+        name: "Pachysolen tannophilus Nuclear",
+        aas: b"FFLLSSSSYY**CC*WLLLAPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"---M---------------M---------------M----------------------------",
+    },
+    CodonTable {
+        id: 27,
+        name: "Karyorelict Nuclear",
+        aas: b"FFLLSSSSYYQQCCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    CodonTable {
+        id: 28,
+        name: "Condylostoma Nuclear",
+        aas: b"FFLLSSSSYYQQCCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    CodonTable {
+        id: 29,
+        name: "Mesodinium Nuclear",
+        aas: b"FFLLSSSSYYYYCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    CodonTable {
+        id: 30,
+        name: "Peritrich Nuclear",
+        aas: b"FFLLSSSSYYEECC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    CodonTable {
+        id: 31,
+        name: "Blastocrithidia Nuclear",
+        aas: b"FFLLSSSSYYEECCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"-----------------------------------M----------------------------",
+    },
+    CodonTable {
+        id: 32,
+        name: "Balanophoraceae Plastid",
+        aas: b"FFLLSSSSYY*WCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        starts: b"---M---------------M------------MMMM---------------M------------",
+    },
+    CodonTable {
+        id: 33,
+        name: "Cephalodiscidae Mitochondrial",
+        aas: b"FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+        starts: b"---M---------------M---------------M---------------M------------",
+    },
+];
+
+/// Bit mask of T C A G for a nucleotide, IUPAC ambiguity code is the union of its bases.
+pub fn base_mask(nt: u8) -> u8 {
+    match nt.to_ascii_uppercase() {
+        b'T' | b'U' => 0b0001,
+        b'C' => 0b0010,
+        b'A' => 0b0100,
+        b'G' => 0b1000,
+        b'Y' => 0b0011,
+        b'W' => 0b0101,
+        b'K' => 0b1001,
+        b'M' => 0b0110,
+        b'S' => 0b1010,
+        b'R' => 0b1100,
+        b'H' => 0b0111,
+        b'B' => 0b1011,
+        b'D' => 0b1101,
+        b'V' => 0b1110,
+        b'N' => 0b1111,
+        _ => 0,
+    }
+}
+
+// three letter name of an amino acid
+fn aa_name(aa: u8) -> &'static str {
+    match aa {
+        b'A' => "Ala",
+        b'R' => "Arg",
+        b'N' => "Asn",
+        b'D' => "Asp",
+        b'C' => "Cys",
+        b'E' => "Glu",
+        b'Q' => "Gln",
+        b'G' => "Gly",
+        b'H' => "His",
+        b'I' => "Ile",
+        b'L' => "Leu",
+        b'K' => "Lys",
+        b'M' => "Met",
+        b'F' => "Phe",
+        b'P' => "Pro",
+        b'S' => "Ser",
+        b'T' => "Thr",
+        b'W' => "Trp",
+        b'Y' => "Tyr",
+        b'V' => "Val",
+        _ => "Stop",
+    }
+}
+
+impl CodonTable {
+    /// Get the NCBI genetic code by its id.
+    pub fn get(id: u8) -> Result<&'static CodonTable, FakitError> {
+        CODON_TABLES.iter().find(|x| x.id == id).ok_or_else(|| {
+            let ids = CODON_TABLES
+                .iter()
+                .map(|x| x.id.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            FakitError::InvalidArgument(format!("no translation table {}, available: {}", id, ids))
+        })
+    }
+
+    // all codon indexes a codon with IUPAC ambiguity code may stand for
+    fn indexes(codon: &[u8]) -> Vec<usize> {
+        let mut indexes = vec![0usize];
+        for &nt in codon {
+            let mask = base_mask(nt);
+            indexes = indexes
+                .iter()
+                .flat_map(|idx| {
+                    (0..4)
+                        .filter(move |i| mask & (1 << i) != 0)
+                        .map(move |i| idx * 4 + i)
+                })
+                .collect();
+        }
+        indexes
+    }
+
+    /// Translate a codon, an ambiguous codon gets the amino acid shared by all the codons it
+    /// stands for, or X. Gap codon "---" gets '-'.
+    pub fn translate(&self, codon: &[u8]) -> u8 {
+        if codon == b"---" {
+            return b'-';
+        }
+        let indexes = Self::indexes(codon);
+        match indexes.first() {
+            Some(&first) if indexes.iter().all(|&i| self.aas[i] == self.aas[first]) => {
+                self.aas[first]
+            }
+            _ => b'X',
+        }
+    }
+
+    /// Returns true if the codon is an initiation codon of the table.
+    pub fn is_start(&self, codon: &[u8]) -> bool {
+        let indexes = Self::indexes(codon);
+        !indexes.is_empty() && indexes.iter().all(|&i| self.starts[i] == b'M')
+    }
+
+    // codon string in rna form at the index
+    fn codon(idx: usize) -> String {
+        [idx / 16, idx / 4 % 4, idx % 4]
+            .iter()
+            .map(|&i| if i == 0 { 'U' } else { BASES[i] as char })
+            .collect()
+    }
+
+    // codons of an amino acid, or stop codons for '*'
+    fn codons_of(&self, aa: u8) -> Vec<String> {
+        (0..64)
+            .filter(|&i| self.aas[i] == aa)
+            .map(Self::codon)
+            .collect()
+    }
+}
+
+pub fn show_codon(name: Option<String>, table: u8) -> Result<(), FakitError> {
+    let table = CodonTable::get(table)?;
 
     if let Some(name) = name {
-        if aa.contains_key(&name) {
-            println!("{}\t{}", name, aa.get(&name).unwrap());
-        } else {
-            error!("no amino acid named {}", name);
-            std::process::exit(1);
+        let codons = match name.as_bytes() {
+            [aa] => table.codons_of(aa.to_ascii_uppercase()),
+            _ => vec![],
+        };
+        if codons.is_empty() {
+            return Err(FakitError::InvalidArgument(format!(
+                "no amino acid named {}",
+                name
+            )));
         }
+        println!("{}\t{}", name, codons.join(","));
     } else {
-        let mut i = 0;
-        for iterm in codons {
-            i += 1;
-            print!("{}\t", iterm);
-            if i % 4 == 0 {
+        println!("Translation table {}: {}", table.id, table.name);
+        for i in 0..64 {
+            let aa = table.aas[i];
+            let item = if aa == b'*' {
+                format!("{} (Stop)", CodonTable::codon(i))
+            } else {
+                format!("{} ({}/{})", CodonTable::codon(i), aa_name(aa), aa as char)
+            };
+            print!("{}\t", item);
+            if (i + 1) % 4 == 0 {
                 println!();
             }
-            if i % 16 == 0 {
+            if (i + 1) % 16 == 0 {
                 println!();
             }
         }
-        println!("Iniation codon: {}", "AUG".green());
-        println!("Termination codon: {}", "UAG,UAA,UGA".red())
+        let starts = (0..64)
+            .filter(|&i| table.starts[i] == b'M')
+            .map(CodonTable::codon)
+            .collect::<Vec<_>>();
+        println!("Iniation codon: {}", starts.join(",").green());
+        println!(
+            "Termination codon: {}",
+            table.codons_of(b'*').join(",").red()
+        )
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translate_standard() {
+        let table = CodonTable::get(1).unwrap();
+        assert_eq!(table.translate(b"ATG"), b'M');
+        assert_eq!(table.translate(b"atg"), b'M');
+        assert_eq!(table.translate(b"AUG"), b'M');
+        assert_eq!(table.translate(b"TAA"), b'*');
+        assert_eq!(table.translate(b"TGA"), b'*');
+        assert_eq!(table.translate(b"---"), b'-');
+    }
+
+    #[test]
+    fn translate_ambiguous() {
+        let table = CodonTable::get(1).unwrap();
+        // all codons stand for the same amino acid
+        assert_eq!(table.translate(b"CTN"), b'L');
+        assert_eq!(table.translate(b"TAR"), b'*');
+        assert_eq!(table.translate(b"YTR"), b'L');
+        // F or L
+        assert_eq!(table.translate(b"TTN"), b'X');
+        assert_eq!(table.translate(b"AT-"), b'X');
+        assert_eq!(table.translate(b"AXG"), b'X');
+    }
+
+    #[test]
+    fn translate_other_tables() {
+        let table = CodonTable::get(2).unwrap();
+        assert_eq!(table.translate(b"TGA"), b'W');
+        assert_eq!(table.translate(b"AGA"), b'*');
+        let table = CodonTable::get(32).unwrap();
+        assert_eq!(table.translate(b"TAG"), b'W');
+        assert_eq!(table.translate(b"TAA"), b'*');
+        assert!(CodonTable::get(7).is_err());
+    }
+
+    #[test]
+    fn start_codons() {
+        let table = CodonTable::get(11).unwrap();
+        assert!(table.is_start(b"ATG"));
+        assert!(table.is_start(b"GTG"));
+        assert!(table.is_start(b"ATH"));
+        assert!(table.is_start(b"ATN"));
+        assert!(!table.is_start(b"TTN"));
+        assert!(!CodonTable::get(1).unwrap().is_start(b"GTG"));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader},
 };
 use log::info;
 use paraseq::{
@@ -9,21 +9,30 @@ use paraseq::{
 };
 use std::{io::BufReader, path::Path};
 
-pub fn fake_quality<P: AsRef<Path> + Copy>(
-    input: Option<P>,
+#[allow(clippy::too_many_arguments)]
+pub fn fake_quality<P: AsRef<Path>>(
+    input: &[P],
+    tag_source: bool,
+    strict: bool,
     qual: char,
     keep: bool,
-    out: Option<P>,
+    out: Option<&P>,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut rdr = file_reader(input).map(BufReader::new).map(Reader::new)?;
+    let mut rdr = multi_reader(input, tag_source, strict)
+        .map(BufReader::new)
+        .map(Reader::new)?;
     let mut rset = RecordSet::default();
 
-    let mut wtr = file_writer(out, compression_level)?;
+    let mut wtr = file_writer(out, compression_level, threads, output_compression)?;
     let qualscore = qual;
 
+    let mut nrec = 0usize;
     while rset.fill(&mut rdr)? {
-        for rec in rset.iter().map_while(Result::ok) {
+        for rec in batch_records(&rset, &mut nrec) {
+            let rec = rec?;
             wtr.write_all(b"@")?;
             if keep {
                 wtr.write_all(rec.id())?;
@@ -1,60 +1,816 @@
-use crate::utils::file_writer;
-use anyhow::{Ok, Result};
-use log::{error, info, warn};
+use crate::{
+    cmd::{reverse::reverse_complement, wrap::write_record},
+    errors::FakitError,
+    remote::{self, RemoteFile},
+    utils::{
+        CompressionFormat, detect_compression, detect_magic, file_reader, file_writer, open_file,
+        peek_magic,
+    },
+};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use log::{info, warn};
+use noodles::bgzf::{self, gzi};
 use noodles::core::{Region, position::Position, region::interval::Interval};
-use noodles::fasta::{self, fai, index, indexed_reader};
-use std::path::{Path, PathBuf};
+use noodles::fasta::{self, fai};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
 
+// gzip header of a BGZF block starts with magic, deflate method and FEXTRA flag, XLEN at 10..12
+const BGZF_MAGIC: [u8; 4] = [0x1f, 0x8b, 0x08, 0x04];
+const GZIP_HEADER_SIZE: usize = 12;
+// CRC32 and ISIZE at the end of a block
+const GZIP_FOOTER_SIZE: usize = 8;
+
+#[derive(Debug)]
+struct FaRegion {
+    chrom: String,
+    // 1-based, inclusive
+    start: Option<usize>,
+    end: Option<usize>,
+    minus: bool,
+    name: Option<String>,
+}
+
+impl FaRegion {
+    // accepted format: chr1, chr1:100, chr1:100-, chr1:-500, chr1:100-500, chr1:1,000-2,000
+    fn parse(reg: &str) -> Result<Self, FakitError> {
+        let reg = reg.trim();
+        if reg.is_empty() {
+            return Err(FakitError::InvalidRegion(reg.to_string()));
+        }
+        let whole = FaRegion {
+            chrom: reg.to_string(),
+            start: None,
+            end: None,
+            minus: false,
+            name: None,
+        };
+        let Some((chrom, range)) = reg.rsplit_once(':') else {
+            return Ok(whole);
+        };
+
+        let parse_pos = |x: &str| -> Option<Option<usize>> {
+            let x = x.replace(',', "");
+            if x.is_empty() {
+                Some(None)
+            } else {
+                x.parse::<usize>().ok().map(Some)
+            }
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((s, e)) => match (parse_pos(s), parse_pos(e)) {
+                (Some(s), Some(e)) => (s, e),
+                // sequence name contains ':', eg. HLA-A*01:01
+                _ => return Ok(whole),
+            },
+            None => match parse_pos(range) {
+                Some(s) => (s, None),
+                None => return Ok(whole),
+            },
+        };
+
+        if chrom.is_empty() || start == Some(0) || end == Some(0) {
+            return Err(FakitError::InvalidRegion(reg.to_string()));
+        }
+        if let (Some(s), Some(e)) = (start, end) {
+            if s > e {
+                return Err(FakitError::InvalidRegion(reg.to_string()));
+            }
+        }
+
+        Ok(FaRegion {
+            chrom: chrom.to_string(),
+            start,
+            end,
+            minus: false,
+            name: None,
+        })
+    }
+
+    // bed line: chrom, 0-based start, end, [name], [score], [strand]
+    fn from_bed(fields: &[&str]) -> Result<Self, FakitError> {
+        let line = fields.join("\t");
+        let start = fields[1].parse::<usize>()?;
+        let end = fields[2].parse::<usize>()?;
+        if start >= end {
+            return Err(FakitError::InvalidRegion(line));
+        }
+        Ok(FaRegion {
+            chrom: fields[0].to_string(),
+            start: Some(start + 1),
+            end: Some(end),
+            minus: fields.get(5).is_some_and(|x| *x == "-"),
+            name: fields
+                .get(3)
+                .filter(|x| !x.is_empty() && **x != ".")
+                .map(|x| x.to_string()),
+        })
+    }
+
+    fn region(&self) -> Region {
+        let interval = match (self.start, self.end) {
+            (Some(s), Some(e)) => {
+                Interval::from(Position::new(s).unwrap()..=Position::new(e).unwrap())
+            }
+            (Some(s), None) => Interval::from(Position::new(s).unwrap()..),
+            (None, Some(e)) => Interval::from(..=Position::new(e).unwrap()),
+            (None, None) => Interval::from(..),
+        };
+        Region::new(self.chrom.as_str(), interval)
+    }
+
+    // check the region against the sequence length in index, end is clamped to the length
+    fn clamp(&mut self, length: usize) -> Result<(), FakitError> {
+        if self.start.is_some_and(|x| x > length) {
+            return Err(FakitError::InvalidRegion(format!(
+                "{}, start is beyond sequence length {}",
+                self.region(),
+                length
+            )));
+        }
+        self.end = self.end.map(|x| x.min(length));
+        Ok(())
+    }
+
+    // placeholder in name format: {chrom} {start} {end} {strand} {name} {region},
+    // end is None if the region is open-ended and no sequence was extracted
+    fn output_name(&self, end: Option<usize>, name_format: Option<&str>) -> String {
+        let start = self.start.unwrap_or(1);
+        let end = end.map_or(String::new(), |x| x.to_string());
+        let region = if self.start.is_none() && self.end.is_none() {
+            self.chrom.clone()
+        } else {
+            format!("{}:{}-{}", self.chrom, start, end)
+        };
+        let strand = if self.minus { "-" } else { "+" };
+
+        match name_format {
+            Some(fmt) => fmt
+                .replace("{chrom}", &self.chrom)
+                .replace("{start}", &start.to_string())
+                .replace("{end}", &end)
+                .replace("{strand}", strand)
+                .replace("{name}", self.name.as_deref().unwrap_or(&region))
+                .replace("{region}", &region),
+            None if self.minus => format!("{}/rc", region),
+            None => region,
+        }
+    }
+}
+
+// local fasta file or remote fasta accessed by http range requests
+pub trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+pub type FastaIndexedReader = fasta::io::IndexedReader<fasta::io::BufReader<Box<dyn ReadSeek>>>;
+
+// raw region string and parsed result
+type RawRegion = (String, Result<FaRegion, FakitError>);
+
+/// how to deal with a region that can't be parsed or extracted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnError {
+    /// skip the region with a warning message
+    Skip,
+    /// stop and report the error
+    Fail,
+    /// output a record with empty sequence
+    Empty,
+}
+
+// a bed file, or a region list file with columns: region, [name], [strand]
+fn read_region_file<P: AsRef<Path> + Copy>(file: P) -> Result<Vec<RawRegion>, FakitError> {
+    let reader = file_reader(Some(file))?;
+    let mut regions = vec![];
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        let is_bed = fields.len() >= 3
+            && fields[1].parse::<usize>().is_ok()
+            && fields[2].parse::<usize>().is_ok();
+
+        let reg = if is_bed {
+            FaRegion::from_bed(&fields)
+        } else {
+            FaRegion::parse(fields[0]).map(|mut reg| {
+                reg.name = fields
+                    .get(1)
+                    .filter(|x| !x.is_empty() && **x != ".")
+                    .map(|x| x.to_string());
+                reg.minus = fields.get(2).is_some_and(|x| *x == "-");
+                reg
+            })
+        };
+        regions.push((line.to_string(), reg));
+    }
+    info!(
+        "total {} regions in file: {}",
+        regions.len(),
+        file.as_ref().display()
+    );
+
+    Ok(regions)
+}
+
+// index is stale when fasta file is modified after index created, or the last record in index
+// points beyond the end of the fasta file
+fn index_is_stale<P: AsRef<Path>>(
+    file: P,
+    format: CompressionFormat,
+    fai: &str,
+    gzi: &str,
+) -> Result<bool> {
+    let fa_meta = std::fs::metadata(&file)?;
+    let mut index_files = vec![fai];
+    if format == CompressionFormat::Bgzf {
+        index_files.push(gzi);
+    }
+    for idx in index_files {
+        if std::fs::metadata(idx)?.modified()? < fa_meta.modified()? {
+            warn!("index file: {} is older than fasta file", idx);
+            return Ok(true);
+        }
+    }
+
+    let data_end = match format {
+        CompressionFormat::Bgzf => gzi::fs::read(gzi)?
+            .as_ref()
+            .last()
+            .map_or(0, |(compressed, _)| *compressed),
+        _ => fai::fs::read(fai)?.as_ref().last().map_or(0, record_end),
+    };
+    if data_end > fa_meta.len() {
+        warn!(
+            "index file points to offset {} beyond fasta file size {}",
+            data_end,
+            fa_meta.len()
+        );
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+// offset after the last base of an indexed sequence
+fn record_end(rec: &fai::Record) -> u64 {
+    let full_lines = rec.length() / rec.line_bases().max(1);
+    let rest = rec.length() % rec.line_bases().max(1);
+    rec.offset() + full_lines * rec.line_width() + rest
+}
+
+/// Header line without ">" of the i-th sequence in index, read between the end of the previous
+/// sequence and the start of this one.
+pub fn read_indexed_header(reader: &mut FastaIndexedReader, i: usize) -> io::Result<Vec<u8>> {
+    let records = reader.index().as_ref();
+    let start = match i {
+        0 => 0,
+        _ => record_end(&records[i - 1]),
+    };
+    let name = records[i].name().to_vec();
+    let len = records[i].offset().saturating_sub(start);
+    reader.get_mut().seek(SeekFrom::Start(start))?;
+    let mut buf = vec![];
+    reader.get_mut().take(len).read_to_end(&mut buf)?;
+
+    Ok(match buf.iter().position(|x| *x == b'>') {
+        Some(pos) => buf[pos + 1..].trim_ascii_end().to_vec(),
+        None => name,
+    })
+}
+
+fn build_index<P: AsRef<Path> + Copy>(
+    file: P,
+    format: CompressionFormat,
+    fai: &str,
+    gzi: &str,
+) -> Result<()> {
+    info!("create index file for: {}", file.as_ref().display());
+    let index = match format {
+        CompressionFormat::Bgzf => {
+            let gzi_index = bgzf_index(file)?;
+            gzi::fs::write(gzi, &gzi_index)?;
+            info!("bgzip index done, write index file to: {}", gzi);
+            let reader = open_file(file).map(bgzf::io::Reader::new)?;
+            fasta_index(reader)?
+        }
+        _ => fasta_index(open_file(file).map(BufReader::new)?)?,
+    };
+    let fai_wtr = file_writer(Some(fai), 0u32, 1, Some(CompressionFormat::Plain))?;
+    let mut faidx_wtr = fai::io::Writer::new(fai_wtr);
+    faidx_wtr.write_index(&index)?;
+    faidx_wtr.get_mut().flush()?;
+    info!("index done, write index file to: {}", fai);
+
+    Ok(())
+}
+
+// make sure the local fasta index exists and is up to date, returns format and index paths
+fn prepare_index<P: AsRef<Path> + Copy>(
+    file: P,
+    rebuild: bool,
+) -> Result<(CompressionFormat, String, String)> {
+    let format = detect_compression(file)?;
+    check_indexable(format)?;
+
+    let fai = format!("{}.fai", file.as_ref().display());
+    let gzi = format!("{}.gzi", file.as_ref().display());
+    let index_exists = PathBuf::from(&fai).exists()
+        && (format == CompressionFormat::Plain || PathBuf::from(&gzi).exists());
+
+    if !index_exists {
+        build_index(file, format, &fai, &gzi)?;
+    } else if index_is_stale(file, format, &fai, &gzi)? {
+        if rebuild {
+            info!("rebuild stale index for: {}", file.as_ref().display());
+            build_index(file, format, &fai, &gzi)?;
+        } else {
+            return Err(FakitError::StaleIndex(fai).into());
+        }
+    } else {
+        info!("fasta index file is exists");
+    }
+
+    Ok((format, fai, gzi))
+}
+
+fn check_indexable(format: CompressionFormat) -> Result<(), FakitError> {
+    match format {
+        CompressionFormat::Plain | CompressionFormat::Bgzf => Ok(()),
+        _ => Err(FakitError::UnsupportedFormat(format!(
+            "{:?}, only uncompressed or bgzip compressed fasta can be indexed, recompress file with: bgzip",
+            format
+        ))),
+    }
+}
+
+// remote fasta must have index files next to it, eg. https://host/ref.fa.gz.fai and .gzi
+fn remote_indexed_reader(url: &str) -> Result<FastaIndexedReader> {
+    let (head, mut fp) = peek_magic(RemoteFile::open(url)?)?;
+    let format = detect_magic(&head);
+    check_indexable(format)?;
+    fp.seek(SeekFrom::Start(0))?;
+
+    let fai = format!("{}.fai", url);
+    let index = fai::io::Reader::new(
+        &remote::read_to_end(&fai).context("remote fasta index file is required")?[..],
+    )
+    .read_index()?;
+    let gzi_index = match format {
+        CompressionFormat::Bgzf => {
+            let gzi = format!("{}.gzi", url);
+            let buf = remote::read_to_end(&gzi).context("remote bgzip index file is required")?;
+            Some(gzi::io::Reader::new(&buf[..]).read_index()?)
+        }
+        _ => None,
+    };
+    info!("remote fasta index loaded: {}", fai);
+
+    Ok(indexed_reader(Box::new(fp), index, gzi_index)?)
+}
+
+/// Indexed reader of a local fasta file whose index files exist and are up to date, or None.
+pub fn existing_indexed_reader<P: AsRef<Path> + Copy>(
+    file: P,
+) -> Result<Option<FastaIndexedReader>, FakitError> {
+    if !file.as_ref().is_file() {
+        return Ok(None);
+    }
+    let format = detect_compression(file)?;
+    if check_indexable(format).is_err() {
+        return Ok(None);
+    }
+    let fai = format!("{}.fai", file.as_ref().display());
+    let gzi = format!("{}.gzi", file.as_ref().display());
+    let index_exists = PathBuf::from(&fai).exists()
+        && (format == CompressionFormat::Plain || PathBuf::from(&gzi).exists());
+    if !index_exists || index_is_stale(file, format, &fai, &gzi).unwrap_or(true) {
+        return Ok(None);
+    }
+
+    let index = fai::fs::read(&fai)?;
+    let gzi_index = match format {
+        CompressionFormat::Bgzf => Some(gzi::fs::read(&gzi)?),
+        _ => None,
+    };
+    Ok(Some(indexed_reader(
+        Box::new(open_file(file)?),
+        index,
+        gzi_index,
+    )?))
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn faidx_fasta<P: AsRef<Path> + Copy>(
     input: Option<P>,
     region: Option<Vec<String>>,
+    region_file: Option<P>,
+    name_format: Option<&str>,
+    rebuild: bool,
+    on_error: OnError,
     output: Option<P>,
+    line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<()> {
-    if let Some(file) = input {
-        let fai = format!("{}.fai", file.as_ref().display());
-        if PathBuf::from(fai.clone()).exists() {
-            info!("fasta index file is exists");
-            if let Some(regs) = region {
-                let mut fa_index_reader =
-                    indexed_reader::Builder::default().build_from_path(file)?;
-                let mut wtr = file_writer(output, compression_level).map(fasta::Writer::new)?;
-
-                for reg in regs {
-                    let info = reg.split(':').collect::<Vec<&str>>();
-                    let pos = info[1].split('-').collect::<Vec<&str>>();
-                    info!(
-                        "parse region {}; id: {}, start: {}, end: {}",
-                        reg, info[0], pos[0], pos[1]
-                    );
-
-                    let start = Position::try_from(pos[0].parse::<usize>()?)?;
-                    let end = Position::try_from(pos[1].parse::<usize>()?)?;
-                    let reg_new = Region::new(info[0], Interval::from(start..=end));
-                    let target = fa_index_reader.query(&reg_new)?;
-                    wtr.write_record(&target)?;
+    let Some(file) = input else {
+        return Err(FakitError::InvalidArgument(
+            "no input fasta file, use opt -h get more help information".to_string(),
+        )
+        .into());
+    };
+
+    let mut regions = vec![];
+    if let Some(regs) = region {
+        for reg in regs {
+            let parsed = FaRegion::parse(&reg);
+            regions.push((reg, parsed));
+        }
+    }
+    if let Some(file) = region_file {
+        regions.extend(read_region_file(file)?);
+    }
+
+    let mut fa_index_reader = match file.as_ref().to_str().filter(|x| remote::is_remote(x)) {
+        Some(url) => {
+            if regions.is_empty() {
+                return Err(FakitError::InvalidArgument(
+                    "index file can't be created for remote fasta, upload the .fai (and .gzi) file next to it".to_string(),
+                )
+                .into());
+            }
+            remote_indexed_reader(url)?
+        }
+        None => {
+            let (format, fai, gzi) = prepare_index(file, rebuild)?;
+            if regions.is_empty() {
+                return Ok(());
+            }
+            let index = fai::fs::read(&fai)?;
+            let gzi_index = match format {
+                CompressionFormat::Bgzf => Some(gzi::fs::read(&gzi)?),
+                _ => None,
+            };
+            indexed_reader(Box::new(open_file(file)?), index, gzi_index)?
+        }
+    };
+    // sequence lengths in index, noodles doesn't check a region against the end of its sequence
+    let lengths = fa_index_reader
+        .index()
+        .as_ref()
+        .iter()
+        .map(|rec| (rec.name().to_vec(), rec.length() as usize))
+        .collect::<HashMap<Vec<u8>, usize>>();
+    let mut wtr = file_writer(output, compression_level, threads, output_compression)?;
+    let mut failed = 0usize;
+
+    for (raw, reg) in regions {
+        // header of an empty record: the region as requested, or the raw region if it is invalid
+        let empty_name = match reg.as_ref() {
+            Ok(reg) => reg.output_name(reg.end, name_format),
+            Err(_) => raw.split('\t').next().unwrap_or_default().to_string(),
+        };
+        let target = reg.and_then(|mut reg| {
+            if let Some(length) = lengths.get(reg.chrom.as_bytes()) {
+                reg.clamp(*length)?;
+            }
+            info!(
+                "parse region id: {}, start: {}, end: {}, strand: {}",
+                reg.chrom,
+                reg.start.map_or("-".to_string(), |x| x.to_string()),
+                reg.end.map_or("-".to_string(), |x| x.to_string()),
+                if reg.minus { "-" } else { "+" },
+            );
+            let target = fa_index_reader
+                .query(&reg.region())
+                .map_err(|e| match e.kind() {
+                    io::ErrorKind::InvalidInput => {
+                        FakitError::InvalidRegion(format!("{}, {}", reg.region(), e))
+                    }
+                    _ => FakitError::IoError(e),
+                })?;
+            Ok((reg, target))
+        });
+
+        match target {
+            Ok((reg, target)) => {
+                let seq = target.sequence().as_ref();
+                let end = reg.start.unwrap_or(1) + seq.len().max(1) - 1;
+                let name = reg.output_name(Some(end), name_format);
+                if reg.minus {
+                    write_record(
+                        &mut wtr,
+                        name.as_bytes(),
+                        &reverse_complement(seq),
+                        line_width,
+                    )?;
+                } else {
+                    write_record(&mut wtr, name.as_bytes(), seq, line_width)?;
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                match on_error {
+                    OnError::Fail => {
+                        wtr.flush()?;
+                        return Err(e)
+                            .with_context(|| format!("failed to extract region: {}", raw));
+                    }
+                    OnError::Skip => warn!("skip region: {}, {}", raw, e),
+                    OnError::Empty => {
+                        warn!("empty record for region: {}, {}", raw, e);
+                        write_record(&mut wtr, empty_name.as_bytes(), b"", line_width)?;
+                    }
                 }
             }
-            std::process::exit(1);
         }
+    }
+    wtr.flush()?;
 
-        if region.is_some() {
-            warn!("index fasta file first and rerun command");
-            std::process::exit(1);
+    if failed > 0 {
+        warn!("total {} regions failed to extract", failed);
+    }
+    Ok(())
+}
+
+fn fasta_index<R: BufRead>(reader: R) -> Result<fai::Index> {
+    let mut indexer = fasta::io::Indexer::new(reader);
+    let mut records = Vec::new();
+    while let Some(record) = indexer.index_record()? {
+        records.push(record);
+    }
+    Ok(fai::Index::from(records))
+}
+
+// walk the BGZF blocks and record (compressed offset, uncompressed offset) of each block start
+fn bgzf_index<P: AsRef<Path>>(file: P) -> Result<gzi::Index> {
+    let reader = open_file(&file).map(BufReader::new)?;
+    bgzf_blocks(reader, file.as_ref())
+}
+
+fn bgzf_blocks<R: Read>(mut reader: R, path: &Path) -> Result<gzi::Index> {
+    let mut blocks = Vec::new();
+    let (mut compressed, mut uncompressed) = (0u64, 0u64);
+
+    loop {
+        let invalid = || FakitError::InvalidBgzfBlock {
+            path: path.to_path_buf(),
+            offset: compressed,
+        };
+        let mut header = Vec::with_capacity(GZIP_HEADER_SIZE);
+        (&mut reader)
+            .take(GZIP_HEADER_SIZE as u64)
+            .read_to_end(&mut header)?;
+        if header.is_empty() {
+            break;
         }
+        if header.len() < GZIP_HEADER_SIZE || header[..4] != BGZF_MAGIC {
+            return Err(invalid().into());
+        }
+        let xlen = u16::from_le_bytes([header[10], header[11]]) as usize;
+        let mut extra = vec![0u8; xlen];
+        read_block_part(&mut reader, &mut extra, invalid)?;
+        // compressed data, CRC32 and ISIZE
+        let rest_size = bgzf_block_size(&extra)
+            .and_then(|x| x.checked_sub(GZIP_HEADER_SIZE + xlen))
+            .filter(|x| *x >= GZIP_FOOTER_SIZE)
+            .ok_or_else(invalid)?;
+        let mut rest = vec![0u8; rest_size];
+        read_block_part(&mut reader, &mut rest, invalid)?;
+        let isize = u32::from_le_bytes(rest[rest_size - 4..].try_into()?) as u64;
 
-        // faidx not exists
-        info!("create index file for: {}", file.as_ref().display());
-        let fai_wtr = file_writer(Some(&fai), 0u32)?;
-        // create index for fasta
-        let index = index(input.unwrap())?;
-        let mut faidx_wtr = fai::Writer::new(fai_wtr);
-        faidx_wtr.write_index(&index)?;
-        info!("index done, write index file to: {}", fai);
-    } else {
-        error!("use opt -h get more help information");
-        std::process::exit(1);
+        if compressed > 0 && isize > 0 {
+            blocks.push((compressed, uncompressed));
+        }
+        compressed += (GZIP_HEADER_SIZE + xlen + rest_size) as u64;
+        uncompressed += isize;
     }
 
-    Ok(())
+    Ok(gzi::Index::from(blocks))
+}
+
+// total block size, BSIZE + 1, from the "BC" subfield in gzip extra field
+fn bgzf_block_size(mut extra: &[u8]) -> Option<usize> {
+    while extra.len() >= 4 {
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let data = extra.get(4..4 + len)?;
+        if extra[..2] == *b"BC" && len == 2 {
+            return Some(u16::from_le_bytes([data[0], data[1]]) as usize + 1);
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+// a block cut short by end of file is invalid
+fn read_block_part<R: Read>(
+    reader: &mut R,
+    buf: &mut [u8],
+    invalid: impl Fn() -> FakitError,
+) -> Result<()> {
+    match reader.read_exact(buf) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(invalid().into()),
+        res => Ok(res?),
+    }
+}
+
+fn indexed_reader(
+    inner: Box<dyn ReadSeek>,
+    index: fai::Index,
+    gzi_index: Option<gzi::Index>,
+) -> io::Result<FastaIndexedReader> {
+    let inner = match gzi_index {
+        Some(gzi_index) => bgzf::io::indexed_reader::Builder::default()
+            .set_index(gzi_index)
+            .build_from_reader(inner)
+            .map(fasta::io::BufReader::Bgzf)?,
+        None => fasta::io::BufReader::Uncompressed(BufReader::new(inner)),
+    };
+    Ok(fasta::io::IndexedReader::new(inner, index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_region() {
+        let reg = FaRegion::parse("chr1:1,000-2,000").unwrap();
+        assert_eq!(reg.chrom, "chr1");
+        assert_eq!((reg.start, reg.end), (Some(1000), Some(2000)));
+
+        let reg = FaRegion::parse("chr1").unwrap();
+        assert_eq!(
+            (reg.chrom.as_str(), reg.start, reg.end),
+            ("chr1", None, None)
+        );
+        let reg = FaRegion::parse("chr1:100-").unwrap();
+        assert_eq!((reg.start, reg.end), (Some(100), None));
+        let reg = FaRegion::parse("chr1:-500").unwrap();
+        assert_eq!((reg.start, reg.end), (None, Some(500)));
+        let reg = FaRegion::parse("chr1:100").unwrap();
+        assert_eq!((reg.start, reg.end), (Some(100), None));
+    }
+
+    #[test]
+    fn parse_region_with_colon_in_name() {
+        let reg = FaRegion::parse("HLA-A*01:01N").unwrap();
+        assert_eq!((reg.chrom.as_str(), reg.start), ("HLA-A*01:01N", None));
+        let reg = FaRegion::parse("HLA-A*01:01:10-20").unwrap();
+        assert_eq!(reg.chrom, "HLA-A*01:01");
+        assert_eq!((reg.start, reg.end), (Some(10), Some(20)));
+    }
+
+    #[test]
+    fn parse_invalid_region() {
+        for reg in ["", ":1-10", "chr1:0-10", "chr1:20-10"] {
+            assert!(FaRegion::parse(reg).is_err(), "{}", reg);
+        }
+    }
+
+    #[test]
+    fn bed_region() {
+        let reg = FaRegion::from_bed(&["chr1", "0", "10", "gene1", "0", "-"]).unwrap();
+        assert_eq!((reg.start, reg.end), (Some(1), Some(10)));
+        assert_eq!(reg.name.as_deref(), Some("gene1"));
+        assert!(reg.minus);
+
+        let reg = FaRegion::from_bed(&["chr1", "5", "10", "."]).unwrap();
+        assert_eq!((reg.start, reg.name, reg.minus), (Some(6), None, false));
+
+        assert!(FaRegion::from_bed(&["chr1", "10", "10"]).is_err());
+        assert!(FaRegion::from_bed(&["chr1", "x", "10"]).is_err());
+    }
+
+    #[test]
+    fn region_clamp() {
+        let mut reg = FaRegion::parse("chr1:100-200").unwrap();
+        reg.clamp(150).unwrap();
+        assert_eq!((reg.start, reg.end), (Some(100), Some(150)));
+        let mut reg = FaRegion::parse("chr1:151-").unwrap();
+        assert!(reg.clamp(150).is_err());
+        let mut reg = FaRegion::parse("chr1").unwrap();
+        reg.clamp(150).unwrap();
+        assert_eq!((reg.start, reg.end), (None, None));
+    }
+
+    #[test]
+    fn region_output_name() {
+        let reg = FaRegion::from_bed(&["chr1", "9", "20", "gene1", "0", "-"]).unwrap();
+        assert_eq!(reg.output_name(Some(20), None), "chr1:10-20/rc");
+        assert_eq!(
+            reg.output_name(Some(20), Some("{name}({strand})")),
+            "gene1(-)"
+        );
+        let reg = FaRegion::parse("chr1:100-").unwrap();
+        assert_eq!(reg.output_name(None, None), "chr1:100-");
+        let reg = FaRegion::parse("chr1").unwrap();
+        assert_eq!(reg.output_name(None, None), "chr1");
+    }
+
+    // bases from a simple lcg, so the sequence does not repeat within a bgzf block
+    fn random_seq(len: usize, mut state: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"ACGT"[(state >> 62) as usize]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bgzf_index_and_query() {
+        let dir = std::env::temp_dir().join(format!("fakit-faidx-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ref.fa.gz");
+        let (seq1, seq2) = (random_seq(150_000, 1), random_seq(100_000, 2));
+        let mut w = file_writer(Some(&path), 6, 1, Some(CompressionFormat::Bgzf)).unwrap();
+        write_record(&mut w, b"s1", &seq1, 60).unwrap();
+        write_record(&mut w, b"s2 desc", &seq2, 60).unwrap();
+        w.flush().unwrap();
+        drop(w);
+
+        let (format, fai, gzi) = prepare_index(&path, false).unwrap();
+        assert_eq!(format, CompressionFormat::Bgzf);
+        assert!(PathBuf::from(&fai).exists() && PathBuf::from(&gzi).exists());
+        assert!(gzi::fs::read(&gzi).unwrap().as_ref().len() > 1);
+
+        let mut reader = existing_indexed_reader(&path).unwrap().unwrap();
+        for (reg, expect) in [
+            ("s1:65001-66000", &seq1[65000..66000]),
+            ("s1:149991-150000", &seq1[149990..]),
+            ("s2:1-100", &seq2[..100]),
+            ("s2:70001-70500", &seq2[70000..70500]),
+        ] {
+            let rec = reader.query(&reg.parse().unwrap()).unwrap();
+            assert_eq!(rec.sequence().as_ref(), expect, "{}", reg);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_bgzf_block() {
+        let path = Path::new("x.fa.gz");
+        let block = |bsize: u16| {
+            let mut x = vec![
+                0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0,
+            ];
+            x.extend(bsize.to_le_bytes());
+            x
+        };
+        // BSIZE smaller than the block header
+        assert!(bgzf_blocks(&block(10)[..], path).is_err());
+        // block shorter than its BSIZE
+        assert!(bgzf_blocks(&block(100)[..], path).is_err());
+        // plain gzip member without "BC" subfield
+        let mut gzip = block(27);
+        gzip[3] = 0;
+        assert!(bgzf_blocks(&gzip[..], path).is_err());
+        let mut other = block(27);
+        other[12] = b'X';
+        assert!(bgzf_blocks(&other[..], path).is_err());
+        assert!(bgzf_blocks(&block(27)[..5], path).is_err());
+
+        // empty block: header, 2 bytes of compressed data, CRC32 and ISIZE
+        let mut empty = block(27);
+        empty.extend([3, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let index = bgzf_blocks(&empty.repeat(2)[..], path).unwrap();
+        assert!(index.as_ref().is_empty());
+        assert!(bgzf_blocks(&[][..], path).unwrap().as_ref().is_empty());
+    }
+
+    #[test]
+    fn indexed_header() {
+        let dir = std::env::temp_dir().join(format!("fakit-faidx-header-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ref.fa");
+        std::fs::write(
+            &path,
+            ">s1 first desc\nACGTACGTAC\nGT\n>s2\nTTTT\n\n>s3\tthird one \nGGGGCCCC\n",
+        )
+        .unwrap();
+        prepare_index(&path, false).unwrap();
+
+        let mut reader = existing_indexed_reader(&path).unwrap().unwrap();
+        for (i, expect) in [(2, "s3\tthird one"), (0, "s1 first desc"), (1, "s2")] {
+            let header = read_indexed_header(&mut reader, i).unwrap();
+            assert_eq!(header, expect.as_bytes());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,73 +1,98 @@
-use crate::{errors::FakitError, utils::file_reader, utils::file_writer};
-use log::info;
-use paraseq::{
-    fasta::{Reader, RecordSet},
-    fastx::Record,
+use crate::{
+    cmd::alphabet::{SeqType, detect_seq_type},
+    errors::FakitError,
+    report::{Cell, ReportFormat, ReportWriter},
+    utils::{CompressionFormat, file_writer, multi_reader, process_records},
 };
-use std::path::Path;
+use log::{info, warn};
+use paraseq::{fasta::Reader, fastx::Record};
+use std::{io::Write, path::Path};
 
 #[allow(clippy::too_many_arguments)]
-pub fn flatten_fa<P: AsRef<Path> + Copy>(
-    file: Option<P>,
+pub fn flatten_fa<P: AsRef<Path>>(
+    file: &[P],
+    tag_source: bool,
+    strict: bool,
     keep: bool,
     gap: bool,
     len: bool,
     gc: bool,
+    seq_type: SeqType,
     sep: char,
-    out: Option<P>,
+    header: bool,
+    format: Option<ReportFormat>,
+    out: Option<&P>,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut reader = file_reader(file).map(Reader::new)?;
-    let mut rset = RecordSet::default();
-
-    let mut writer = file_writer(out, compression_level)?;
-    let mut count = 0usize;
+    let (seq_type, reader) = detect_seq_type(multi_reader(file, tag_source, strict)?, seq_type)?;
+    let reader = Reader::new(reader);
+    if gc && seq_type.is_protein() {
+        warn!("gc content is not available for protein sequences, NA is written instead");
+    }
 
-    while rset.fill(&mut reader)? {
-        for rec in rset.iter().map_while(Result::ok) {
-            count += 1;
-            if keep {
-                writer.write_all(rec.id())?;
-                writer.write_all(sep.to_string().as_bytes())?;
-            } else {
-                let mut id_split = rec.id_str().split_whitespace();
-                if let Some(first_id) = id_split.next() {
-                    writer.write_all(first_id.as_bytes())?;
-                    writer.write_all(sep.to_string().as_bytes())?;
-                }
+    // without --format the plain layout is kept: custom separator and no header
+    let mut writer = ReportWriter::new(
+        file_writer(out, compression_level, threads, output_compression)?,
+        format.unwrap_or(ReportFormat::Tsv),
+    );
+    if let Some(format) = format.filter(|_| header) {
+        let mut columns = vec!["id"];
+        for (flag, name) in [(gap, "gap"), (len, "length"), (gc, "gc")] {
+            if flag {
+                columns.push(name);
             }
+        }
+        columns.push("sequence");
+        format.write_header(&mut writer, &columns)?;
+    }
 
-            if gap {
-                let gap_count = rec
-                    .seq()
-                    .iter()
-                    .filter(|&&c| c == b'N' || c == b'n')
-                    .count();
-                writer.write_all(gap_count.to_string().as_bytes())?;
-                writer.write_all(sep.to_string().as_bytes())?;
-            }
-            if len {
-                let seq_len = rec.seq().len();
-                writer.write_all(seq_len.to_string().as_bytes())?;
-                writer.write_all(sep.to_string().as_bytes())?;
-            }
-            if gc {
-                let gc_count = rec
-                    .seq()
-                    .iter()
-                    .filter(|&c| *c == b'G' || *c == b'C' || *c == b'g' || *c == b'c')
-                    .count();
-                let gc_rate = format!("{:.2}", gc_count as f64 / rec.seq().len() as f64 * 100.0);
-                writer.write_all(gc_rate.as_bytes())?;
-                writer.write_all(sep.to_string().as_bytes())?;
-            }
+    let count = process_records(reader, threads, &mut writer, |rec, buf| {
+        let id = if keep {
+            rec.id_str()
+        } else {
+            rec.id_str().split_whitespace().next().unwrap_or("")
+        };
+        let mut row = vec![("id", Cell::from(id))];
 
-            // write seqquence part
-            writer.write_all(rec.seq().as_ref())?;
-            writer.write_all(b"\n")?;
+        if gap {
+            // unknown residue is X in protein sequence
+            let unknown = if seq_type.is_protein() { b'X' } else { b'N' };
+            let gap_count = rec
+                .seq()
+                .iter()
+                .filter(|c| c.to_ascii_uppercase() == unknown)
+                .count();
+            row.push(("gap", Cell::from(gap_count)));
         }
-    }
-    writer.flush()?;
+        if len {
+            row.push(("length", Cell::from(rec.seq().len())));
+        }
+        if gc && seq_type.is_protein() {
+            row.push(("gc", Cell::Null));
+        } else if gc {
+            let gc_count = rec
+                .seq()
+                .iter()
+                .filter(|&c| *c == b'G' || *c == b'C' || *c == b'g' || *c == b'c')
+                .count();
+            let gc_rate = gc_count as f64 / rec.seq().len() as f64 * 100.0;
+            row.push(("gc", Cell::Float(gc_rate, 2)));
+        }
+        row.push(("sequence", Cell::Str(rec.seq_str().to_string())));
+
+        match format {
+            Some(format) => format.write_row(buf, &row)?,
+            None => {
+                let fields = row.iter().map(|(_, v)| v.text()).collect::<Vec<_>>();
+                buf.write_all(fields.join(&sep.to_string()).as_bytes())?;
+                buf.write_all(b"\n")?;
+            }
+        }
+        Ok(true)
+    })?;
+    writer.finish()?;
 
     info!("strip sequence number: {}", count);
     Ok(())
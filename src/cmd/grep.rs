@@ -1,75 +1,498 @@
 use crate::{
-    cmd::wrap::write_record,
+    cmd::{
+        faidx::{FastaIndexedReader, existing_indexed_reader, read_indexed_header},
+        search::Motif,
+        wrap::write_record,
+    },
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    utils::{CompressionFormat, file_reader, file_writer, multi_reader, process_records},
 };
-use log::{error, info};
-use paraseq::{
-    fasta::{Reader, RecordSet},
-    fastx::Record,
+use log::{info, warn};
+use noodles::core::Region;
+use paraseq::{fasta::Reader, fastx::Record};
+use parking_lot::Mutex;
+use regex::{Regex, RegexBuilder};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufRead, Write},
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
 };
-use regex::RegexBuilder;
-use std::path::Path;
+
+// sequence criterion: a regex, or IUPAC motifs searched on both strands
+enum SeqMatcher {
+    Regex(Regex),
+    Motifs(Vec<Motif>),
+}
+
+// matched record kept for list order output: header and sequence
+type Kept = (Vec<u8>, Vec<u8>);
+
+// ids from a list file, the first token of each line, or the whole line with full_header
+fn read_id_list<P: AsRef<Path> + Copy>(
+    path: P,
+    full_header: bool,
+) -> Result<Vec<String>, FakitError> {
+    let mut ids = vec![];
+    let mut seen = HashSet::new();
+    let mut dup = 0usize;
+    for line in file_reader(Some(path))?.lines() {
+        let line = line?;
+        let line = line.trim().trim_start_matches('>');
+        let id = if full_header {
+            line
+        } else {
+            line.split_whitespace().next().unwrap_or("")
+        };
+        if id.is_empty() {
+            continue;
+        }
+        if !seen.insert(id.to_string()) {
+            dup += 1;
+            continue;
+        }
+        ids.push(id.to_string());
+    }
+    if dup > 0 {
+        warn!("{} duplicate ids in list file are ignored", dup);
+    }
+    info!(
+        "total {} ids in list file: {}",
+        ids.len(),
+        path.as_ref().display()
+    );
+    Ok(ids)
+}
+
+// records of the listed ids read by index in list order, without scanning the input,
+// returns the number of records written and ids not in index
+fn grep_indexed_list<'a, W: Write + Send>(
+    reader: &mut FastaIndexedReader,
+    ids: &'a [String],
+    writer: &mut W,
+    line_width: usize,
+) -> Result<(usize, Vec<&'a String>), FakitError> {
+    let positions = reader
+        .index()
+        .as_ref()
+        .iter()
+        .enumerate()
+        .map(|(i, rec)| (rec.name().to_vec(), i))
+        .collect::<HashMap<Vec<u8>, usize>>();
+    let mut counter = 0usize;
+    let mut missing = vec![];
+    for id in ids {
+        let Some(&i) = positions.get(id.as_bytes()) else {
+            missing.push(id);
+            continue;
+        };
+        let header = read_indexed_header(reader, i)?;
+        let rec = reader.query(&Region::new(id.as_str(), ..))?;
+        write_record(writer, &header, rec.sequence().as_ref(), line_width)?;
+        counter += 1;
+    }
+    Ok((counter, missing))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn grep_id_list<P: AsRef<Path>>(
+    file: &[P],
+    tag_source: bool,
+    strict: bool,
+    out: Option<&P>,
+    id_file: &P,
+    full_header: bool,
+    invert: bool,
+    count: bool,
+    list_order: bool,
+    unmatched: Option<&P>,
+    line_width: usize,
+    compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
+) -> Result<(), FakitError> {
+    if tag_source {
+        return Err(FakitError::InvalidArgument(
+            "--tag-source adds file name to record ids, it can't be used with -f (--id-file)"
+                .to_string(),
+        ));
+    }
+    let ids = read_id_list(id_file, full_header)?;
+    let mut writer = file_writer(out, compression_level, threads, output_compression)?;
+
+    // a single indexed input is read by index for list order output
+    let index_reader = match file {
+        [single] if list_order && !full_header => existing_indexed_reader(single)?,
+        _ => None,
+    };
+    let (counter, missing) = match index_reader {
+        Some(mut index_reader) => {
+            info!("sequences are read by fasta index for list order output");
+            grep_indexed_list(&mut index_reader, &ids, &mut writer, line_width)?
+        }
+        None => {
+            if list_order {
+                warn!(
+                    "no fasta index found, matched records are kept in memory for list order output"
+                );
+            }
+            let lookup = ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| (id.as_str(), i))
+                .collect::<HashMap<&str, usize>>();
+            let found = ids
+                .iter()
+                .map(|_| AtomicBool::new(false))
+                .collect::<Vec<_>>();
+            let kept: Mutex<Vec<Option<Kept>>> = Mutex::new(vec![None; ids.len()]);
+
+            let reader = multi_reader(file, tag_source, strict).map(Reader::new)?;
+            let counter = process_records(reader, threads, &mut writer, |rec, buf| {
+                let key = if full_header {
+                    rec.id_str()
+                } else {
+                    rec.id_str().split_whitespace().next().unwrap_or("")
+                };
+                let hit = lookup.get(key).copied();
+                if let Some(i) = hit {
+                    found[i].store(true, Ordering::Relaxed);
+                }
+                if hit.is_some() == invert {
+                    return Ok(false);
+                }
+                match hit {
+                    Some(i) if list_order => {
+                        kept.lock()[i].get_or_insert((rec.id().to_vec(), rec.seq().to_vec()));
+                    }
+                    _ if count => {}
+                    _ => write_record(buf, rec.id(), &rec.seq(), line_width)?,
+                }
+                Ok(true)
+            })?;
+            if count {
+                writeln!(writer, "{}", counter)?;
+            }
+            for (header, seq) in kept.into_inner().into_iter().flatten() {
+                write_record(&mut writer, &header, &seq, line_width)?;
+            }
+
+            let missing = ids
+                .iter()
+                .zip(found.iter())
+                .filter(|(_, found)| !found.load(Ordering::Relaxed))
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            (counter, missing)
+        }
+    };
+    writer.flush()?;
+
+    if !missing.is_empty() {
+        warn!("{} ids in list file not found in input", missing.len());
+    }
+    // the unmatched id list is always plain text
+    if let Some(path) = unmatched {
+        let mut wtr = file_writer(Some(path), 0, 1, Some(CompressionFormat::Plain))?;
+        for id in missing {
+            writeln!(wtr, "{}", id)?;
+        }
+        wtr.flush()?;
+    }
+
+    info!("total match sequences number: {}", counter);
+    Ok(())
+}
 
 #[allow(clippy::too_many_arguments)]
-pub fn grep_fasta<P: AsRef<Path> + Copy>(
-    file: Option<P>,
-    out: Option<P>,
-    pat: &str,
+pub fn grep_fasta<P: AsRef<Path>>(
+    file: &[P],
+    tag_source: bool,
+    strict: bool,
+    out: Option<&P>,
+    pat: Option<&str>,
+    seq_pat: Option<&str>,
     case: bool,
     by_id: bool,
+    by_desc: bool,
     by_seq: bool,
+    and: bool,
+    degenerate: bool,
+    invert: bool,
+    count: bool,
+    id_file: Option<&P>,
+    full_header: bool,
+    list_order: bool,
+    unmatched: Option<&P>,
     line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut reader = file_reader(file).map(Reader::new)?;
-    let mut rset = RecordSet::default();
-    let mut writer = file_writer(out, compression_level)?;
-
-    let mut flag = 0usize;
-    if by_id {
-        flag += 1;
-    }
-    if by_seq {
-        flag += 1;
+    if let Some(id_file) = id_file {
+        return grep_id_list(
+            file,
+            tag_source,
+            strict,
+            out,
+            id_file,
+            full_header,
+            invert,
+            count,
+            list_order,
+            unmatched,
+            line_width,
+            compression_level,
+            threads,
+            output_compression,
+        );
     }
 
-    if flag == 0 {
-        error!("please specify one of the flags: -n (--by-name) or -s (--by-seq)");
-        std::process::exit(1);
-    } else if flag > 1 {
-        error!("only one of the flags -n (--by-name) or -s (--by-seq), is allowed");
-        std::process::exit(1);
+    let by_seq = by_seq || seq_pat.is_some();
+    if !(by_id || by_desc || by_seq) {
+        return Err(FakitError::InvalidArgument(
+            "please specify at least one of the flags: -n (--by-name), -d (--by-desc) or -s (--by-seq)"
+                .to_string(),
+        ));
     }
+    let build = |pat: &str| {
+        RegexBuilder::new(pat)
+            .case_insensitive(case)
+            .unicode(true)
+            .build()
+    };
+    let name_re = if by_id || by_desc {
+        let Some(pat) = pat else {
+            return Err(FakitError::InvalidArgument(
+                "please specify a pattern with -p (--pattern) to grep by name or description"
+                    .to_string(),
+            ));
+        };
+        info!("regex pattern is: {}", pat);
+        Some(build(pat)?)
+    } else {
+        None
+    };
+    let seq_matcher = match seq_pat.or(pat) {
+        Some(pat) if by_seq && degenerate => {
+            info!("degenerate motif is: {}", pat);
+            let motifs = pat
+                .split('|')
+                .map(Motif::new)
+                .collect::<Result<Vec<Motif>, FakitError>>()?;
+            Some(SeqMatcher::Motifs(motifs))
+        }
+        Some(pat) if by_seq => {
+            info!("sequence regex pattern is: {}", pat);
+            Some(SeqMatcher::Regex(build(pat)?))
+        }
+        Some(_) => None,
+        None => {
+            return Err(FakitError::InvalidArgument(
+                "please specify a pattern with -p (--pattern) or --seq-pattern".to_string(),
+            ));
+        }
+    };
 
-    info!("regex pattern is: {}", pat);
-    let re = RegexBuilder::new(pat)
-        .case_insensitive(case)
-        .unicode(true)
-        .build()?;
-    let mut counter = 0usize;
-    if by_seq {
-        while rset.fill(&mut reader)? {
-            for rec in rset.iter().map_while(Result::ok) {
-                if re.captures(&rec.seq_str()).is_some() {
-                    counter += 1;
-                    write_record(&mut writer, rec.id(), &rec.seq(), line_width)?;
-                }
+    let reader = multi_reader(file, tag_source, strict).map(Reader::new)?;
+    let mut writer = file_writer(out, compression_level, threads, output_compression)?;
+    let counter = process_records(reader, threads, &mut writer, |rec, buf| {
+        let header = rec.id_str();
+        let mut results = vec![];
+        if let Some(re) = name_re.as_ref() {
+            if by_id {
+                results.push(re.is_match(header));
+            }
+            if by_desc {
+                let desc = header.split_once(char::is_whitespace).map_or("", |x| x.1);
+                results.push(re.is_match(desc.trim_start()));
             }
         }
-    }
-    if by_id {
-        while rset.fill(&mut reader)? {
-            for rec in rset.iter().map_while(Result::ok) {
-                if re.captures(rec.id_str()).is_some() {
-                    counter += 1;
-                    write_record(&mut writer, rec.id(), &rec.seq(), line_width)?;
-                }
+        match seq_matcher.as_ref() {
+            Some(SeqMatcher::Regex(re)) => results.push(re.is_match(&rec.seq_str())),
+            Some(SeqMatcher::Motifs(motifs)) => {
+                let seq = rec.seq();
+                results.push(motifs.iter().any(|x| x.is_match(&seq)));
             }
+            None => {}
+        }
+        let matched = if and {
+            results.iter().all(|x| *x)
+        } else {
+            results.iter().any(|x| *x)
+        };
+        if matched == invert {
+            return Ok(false);
+        }
+        if !count {
+            write_record(buf, rec.id(), &rec.seq(), line_width)?;
         }
+        Ok(true)
+    })?;
+    if count {
+        writeln!(writer, "{}", counter)?;
     }
     writer.flush()?;
 
     info!("total match sequences number: {}", counter);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noodles::fasta::{self, fai};
+    use std::path::PathBuf;
+
+    const FASTA: &str = ">s1 kinase domain\nACGTGAATCAAA\n>s2 GANTC site\nTTTTTTTT\n\
+        >s3 hypothetical protein\nGGGATTCCC\n>s4\nCCCC\n";
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fakit-grep-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("in.fa"), FASTA).unwrap();
+        dir
+    }
+
+    // run grep on in.fa of dir with short flags as on the command line, returns the output
+    fn grep(
+        dir: &Path,
+        pat: Option<&str>,
+        seq_pat: Option<&str>,
+        flags: &str,
+        id_file: Option<&str>,
+    ) -> Result<String, FakitError> {
+        let input = [dir.join("in.fa")];
+        let out = dir.join("out.fa");
+        let id_file = id_file.map(|ids| {
+            let path = dir.join("ids.txt");
+            std::fs::write(&path, ids).unwrap();
+            path
+        });
+        let unmatched = dir.join("unmatched.txt");
+        let flag = |x: char| flags.contains(x);
+        grep_fasta(
+            &input,
+            flag('t'),
+            false,
+            Some(&out),
+            pat,
+            seq_pat,
+            false,
+            flag('n'),
+            flag('d'),
+            flag('s'),
+            flag('A'),
+            flag('D'),
+            flag('v'),
+            flag('c'),
+            id_file.as_ref(),
+            false,
+            flag('l'),
+            Some(&unmatched),
+            60,
+            6,
+            1,
+            None,
+        )?;
+        Ok(std::fs::read_to_string(&out).unwrap())
+    }
+
+    fn ids(out: &str) -> Vec<&str> {
+        out.lines()
+            .filter_map(|x| x.strip_prefix('>'))
+            .map(|x| x.split_whitespace().next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn name_and_sequence_combination() {
+        let dir = test_dir("combination");
+        let (pat, seq_pat) = (Some("s[23]"), Some("CCC"));
+        let out = grep(&dir, pat, seq_pat, "ns", None).unwrap();
+        assert_eq!(ids(&out), ["s2", "s3", "s4"]);
+        let out = grep(&dir, pat, seq_pat, "nsA", None).unwrap();
+        assert_eq!(ids(&out), ["s3"]);
+        let out = grep(&dir, Some("kinase"), None, "nd", None).unwrap();
+        assert_eq!(ids(&out), ["s1"]);
+        // the name is not part of the description
+        let out = grep(&dir, Some("s1"), None, "d", None).unwrap();
+        assert!(ids(&out).is_empty());
+        assert!(grep(&dir, Some("s1"), None, "", None).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn count_and_invert() {
+        let dir = test_dir("count");
+        let out = grep(&dir, Some("s1"), None, "nv", None).unwrap();
+        assert_eq!(ids(&out), ["s2", "s3", "s4"]);
+        assert_eq!(grep(&dir, Some("s1"), None, "nc", None).unwrap(), "1\n");
+        assert_eq!(grep(&dir, Some("s1"), None, "ncv", None).unwrap(), "3\n");
+        assert_eq!(grep(&dir, Some("x"), None, "nc", None).unwrap(), "0\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn degenerate_motif_and_description() {
+        let dir = test_dir("degenerate");
+        // GANTC hits GAATC in s1 and GATTC in s3
+        let out = grep(&dir, Some("GANTC"), None, "sD", None).unwrap();
+        assert_eq!(ids(&out), ["s1", "s3"]);
+        // description is matched by the regex, N is a literal letter there
+        let out = grep(&dir, Some("GANTC"), None, "dD", None).unwrap();
+        assert_eq!(ids(&out), ["s2"]);
+        let out = grep(&dir, Some("GANTC"), None, "dsD", None).unwrap();
+        assert_eq!(ids(&out), ["s1", "s2", "s3"]);
+        assert!(
+            grep(&dir, Some("GANTC"), None, "dsDA", None)
+                .unwrap()
+                .is_empty()
+        );
+        // reverse strand hit of GTGA in s1 only
+        let out = grep(&dir, None, Some("TCAC"), "sD", None).unwrap();
+        assert_eq!(ids(&out), ["s1"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn id_list_invert_and_unmatched() {
+        let dir = test_dir("id-list");
+        let list = "s3\nmissing\ns1 first word only\n";
+        let out = grep(&dir, None, None, "", Some(list)).unwrap();
+        assert_eq!(ids(&out), ["s1", "s3"]);
+        let unmatched = std::fs::read_to_string(dir.join("unmatched.txt")).unwrap();
+        assert_eq!(unmatched, "missing\n");
+
+        let out = grep(&dir, None, None, "v", Some(list)).unwrap();
+        assert_eq!(ids(&out), ["s2", "s4"]);
+        assert_eq!(grep(&dir, None, None, "vc", Some(list)).unwrap(), "2\n");
+        let unmatched = std::fs::read_to_string(dir.join("unmatched.txt")).unwrap();
+        assert_eq!(unmatched, "missing\n");
+
+        // source file name prefix never matches a listed id
+        assert!(matches!(
+            grep(&dir, None, None, "t", Some(list)),
+            Err(FakitError::InvalidArgument(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn id_list_order() {
+        let dir = test_dir("list-order");
+        let list = "s4\ns3\nmissing\ns1\n";
+        let expect = ">s4\nCCCC\n>s3 hypothetical protein\nGGGATTCCC\n\
+            >s1 kinase domain\nACGTGAATCAAA\n";
+        // kept in memory without index
+        assert_eq!(grep(&dir, None, None, "l", Some(list)).unwrap(), expect);
+
+        // read by index, description taken from the header line
+        let index = fasta::fs::index(dir.join("in.fa")).unwrap();
+        fai::fs::write(dir.join("in.fa.fai"), &index).unwrap();
+        assert_eq!(grep(&dir, None, None, "l", Some(list)).unwrap(), expect);
+        let unmatched = std::fs::read_to_string(dir.join("unmatched.txt")).unwrap();
+        assert_eq!(unmatched, "missing\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,44 +1,396 @@
-use crate::{errors::FakitError, utils::file_reader, utils::file_writer};
+use crate::{
+    cmd::kmerdb::{KmerDb, dump_kmers},
+    errors::FakitError,
+    report::{Cell, ReportFormat, ReportWriter},
+    utils::{CompressionFormat, file_writer, multi_reader, process_records},
+};
+use clap::ValueEnum;
 use log::info;
-use paraseq::fasta::{Reader, RecordSet};
-use std::collections::HashMap;
+use paraseq::fasta::Reader;
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    io::{self, Read},
+};
 
+// kmers buffered for a shard before taking its lock
+const SHARD_BUFFER: usize = 4096;
+
+/// order of kmer output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum KmerSort {
+    /// kmer sequence in lexicographic order
+    Kmer,
+    /// count from high to low, ties in kmer order
+    Count,
+}
+
+/// An unsigned integer holding a 2-bit packed kmer, A=0 C=1 G=2 T=3, the first base in the
+/// highest bits, so integer order is lexicographic order of kmers of the same length.
+pub trait Kmer: Copy + Ord + Hash + Send + Sync {
+    /// the longest kmer fits in the integer
+    const MAX_K: usize;
+    /// kmer with the base appended to the end and the first base dropped
+    fn push(self, code: u8, k: usize) -> Self;
+    /// reverse complement kmer with the complement base put in front and the last base dropped
+    fn push_rc(self, code: u8, k: usize) -> Self;
+    /// 2-bit code of base i
+    fn code_at(self, i: usize, k: usize) -> u8;
+    /// shard of the kmer in sharded tables
+    fn shard(self, n: usize) -> usize;
+    fn to_u128(self) -> u128;
+    fn from_u128(x: u128) -> Self;
+}
+
+macro_rules! impl_kmer {
+    ($t:ty) => {
+        impl Kmer for $t {
+            const MAX_K: usize = <$t>::BITS as usize / 2;
+
+            fn push(self, code: u8, k: usize) -> Self {
+                let kmer = (self << 2) | code as $t;
+                if k == Self::MAX_K {
+                    kmer
+                } else {
+                    kmer & ((1 << (2 * k)) - 1)
+                }
+            }
+
+            fn push_rc(self, code: u8, k: usize) -> Self {
+                (self >> 2) | (((3 - code) as $t) << (2 * (k - 1)))
+            }
+
+            fn code_at(self, i: usize, k: usize) -> u8 {
+                ((self >> (2 * (k - 1 - i))) & 3) as u8
+            }
+
+            fn shard(self, n: usize) -> usize {
+                let x = self as u128;
+                let h = ((x ^ (x >> 64)) as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                (h >> 32) as usize % n
+            }
+
+            fn to_u128(self) -> u128 {
+                self as u128
+            }
+
+            fn from_u128(x: u128) -> Self {
+                x as $t
+            }
+        }
+    };
+}
+
+impl_kmer!(u64);
+impl_kmer!(u128);
+
+// 2-bit code of a base, case insensitive, U is read as T
+fn base_code(nt: u8) -> Option<u8> {
+    match nt {
+        b'A' | b'a' => Some(0),
+        b'C' | b'c' => Some(1),
+        b'G' | b'g' => Some(2),
+        b'T' | b't' | b'U' | b'u' => Some(3),
+        _ => None,
+    }
+}
+
+/// Call f on each kmer of the sequence, kmers containing bases other than ACGT are skipped.
+/// Canonical kmer is the smaller one of a kmer and its reverse complement.
+pub fn for_each_kmer<K: Kmer + Default>(
+    seq: &[u8],
+    k: usize,
+    canonical: bool,
+    mut f: impl FnMut(K),
+) {
+    let (mut fwd, mut rev) = (K::default(), K::default());
+    // length of the ACGT run ending at current base
+    let mut valid = 0usize;
+    for &nt in seq {
+        let Some(code) = base_code(nt) else {
+            valid = 0;
+            continue;
+        };
+        fwd = fwd.push(code, k);
+        if canonical {
+            rev = rev.push_rc(code, k);
+        }
+        valid += 1;
+        if valid >= k {
+            f(if canonical { fwd.min(rev) } else { fwd });
+        }
+    }
+}
+
+/// Encode a kmer of k bases, None if it has other bases than ACGT.
+pub fn encode_kmer<K: Kmer + Default>(kmer: &[u8], k: usize, canonical: bool) -> Option<K> {
+    let mut encoded = None;
+    if kmer.len() == k {
+        for_each_kmer(kmer, k, canonical, |x| encoded = Some(x));
+    }
+    encoded
+}
+
+/// Decode a packed kmer into bases.
+pub fn kmer_string<K: Kmer>(kmer: K, k: usize) -> String {
+    (0..k)
+        .map(|i| b"ACGT"[kmer.code_at(i, k) as usize] as char)
+        .collect()
+}
+
+/// Count kmers with worker threads, each thread adds kmers into shared tables split by kmer hash.
+/// Returns kmers and counts in kmer order.
+pub fn count_kmers<K: Kmer + Default, R: Read + Send>(
+    reader: Reader<R>,
+    k: usize,
+    canonical: bool,
+    threads: usize,
+) -> Result<Vec<(K, u64)>, FakitError> {
+    let nshard = threads.max(1) * 4;
+    let shards = (0..nshard)
+        .map(|_| Mutex::new(HashMap::<K, u64>::new()))
+        .collect::<Vec<_>>();
+    let flush = |i: usize, buf: &mut Vec<K>| {
+        let mut shard = shards[i].lock();
+        for kmer in buf.drain(..) {
+            *shard.entry(kmer).or_insert(0) += 1;
+        }
+    };
+
+    let nseq = process_records(reader, threads, &mut io::sink(), |rec, _| {
+        let mut bufs: Vec<Vec<K>> = vec![vec![]; nshard];
+        for_each_kmer(&rec.seq(), k, canonical, |kmer: K| {
+            let i = kmer.shard(nshard);
+            bufs[i].push(kmer);
+            if bufs[i].len() >= SHARD_BUFFER {
+                flush(i, &mut bufs[i]);
+            }
+        });
+        for (i, buf) in bufs.iter_mut().enumerate() {
+            if !buf.is_empty() {
+                flush(i, buf);
+            }
+        }
+        Ok(true)
+    })?;
+    info!("total sequences counted: {}", nseq);
+
+    let mut table = shards
+        .into_iter()
+        .flat_map(|shard| shard.into_inner())
+        .collect::<Vec<(K, u64)>>();
+    table.sort_unstable_by_key(|x| x.0);
+    Ok(table)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_kmers<K: Kmer + Default, R: Read + Send>(
+    reader: Reader<R>,
+    kmer_len: usize,
+    canonical: bool,
+    min_count: u64,
+    max_count: Option<u64>,
+    sort: KmerSort,
+    hist: bool,
+    dump: Option<&String>,
+    header: bool,
+    format: ReportFormat,
+    output: Option<&String>,
+    compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
+) -> Result<(), FakitError> {
+    let mut table = count_kmers::<K, R>(reader, kmer_len, canonical, threads)?;
+    let total = table.iter().map(|x| x.1).sum::<u64>();
+    info!("total kmers: {}, distinct kmers: {}", total, table.len());
+    table.retain(|(_, count)| *count >= min_count && max_count.is_none_or(|max| *count <= max));
+    if let Some(path) = dump {
+        let db = KmerDb {
+            k: kmer_len,
+            canonical,
+            table,
+        };
+        return dump_kmers(&db, path, compression_level, threads, output_compression);
+    }
+
+    let mut writer = ReportWriter::new(
+        file_writer(output, compression_level, threads, output_compression)?,
+        format,
+    );
+    if hist {
+        // kmer spectrum: number of distinct kmers seen at each frequency
+        let mut spectrum = BTreeMap::new();
+        for (_, count) in table.iter() {
+            *spectrum.entry(*count).or_insert(0u64) += 1;
+        }
+        if header {
+            format.write_header(&mut writer, &["frequency", "kmers"])?;
+        }
+        for (freq, n) in spectrum {
+            let row = [("frequency", Cell::from(freq)), ("kmers", Cell::from(n))];
+            format.write_row(&mut writer, &row)?;
+        }
+    } else {
+        if sort == KmerSort::Count {
+            table.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        }
+        if header {
+            format.write_header(&mut writer, &["kmer", "count"])?;
+        }
+        for (kmer, count) in table.iter() {
+            let row = [
+                ("kmer", Cell::Str(kmer_string(*kmer, kmer_len))),
+                ("count", Cell::from(*count)),
+            ];
+            format.write_row(&mut writer, &row)?;
+        }
+    }
+    writer.finish()?;
+
+    info!("total kmers written: {}", table.len());
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn kmer_count(
-    input: Option<&String>,
+    input: &[String],
+    tag_source: bool,
+    strict: bool,
     kmer_len: usize,
+    canonical: bool,
+    min_count: u64,
+    max_count: Option<u64>,
+    sort: KmerSort,
+    hist: bool,
+    dump: Option<&String>,
     header: bool,
+    format: ReportFormat,
     output: Option<&String>,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut reader = file_reader(input).map(Reader::new)?;
-    let mut rset = RecordSet::default();
-    let mut writer = file_writer(output, compression_level)?;
-    let mut kmers = HashMap::new();
-    info!("Kmer counting with kmer length: {}", kmer_len);
-    let mut count = 0usize;
+    if kmer_len == 0 || kmer_len > u128::MAX_K {
+        return Err(FakitError::InvalidArgument(format!(
+            "kmer size should be in 1-{}",
+            u128::MAX_K
+        )));
+    }
+    let reader = multi_reader(input, tag_source, strict).map(Reader::new)?;
+    info!(
+        "Kmer counting with kmer length: {}, canonical: {}",
+        kmer_len, canonical
+    );
+
+    // kmers up to 32 bases fit in u64, which halves the table memory
+    if kmer_len <= u64::MAX_K {
+        write_kmers::<u64, _>(
+            reader,
+            kmer_len,
+            canonical,
+            min_count,
+            max_count,
+            sort,
+            hist,
+            dump,
+            header,
+            format,
+            output,
+            compression_level,
+            threads,
+            output_compression,
+        )
+    } else {
+        write_kmers::<u128, _>(
+            reader,
+            kmer_len,
+            canonical,
+            min_count,
+            max_count,
+            sort,
+            hist,
+            dump,
+            header,
+            format,
+            output,
+            compression_level,
+            threads,
+            output_compression,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kmers<K: Kmer + Default>(seq: &[u8], k: usize, canonical: bool) -> Vec<String> {
+        let mut found = vec![];
+        for_each_kmer(seq, k, canonical, |x: K| found.push(kmer_string(x, k)));
+        found
+    }
 
-    while rset.fill(&mut reader)? {
-        for rec in rset.iter().map_while(Result::ok) {
-            let seq = rec.seq();
-            if seq.len() < kmer_len {
-                continue;
+    // canonical kmers by string comparison of both strands
+    fn naive_kmers(seq: &[u8], k: usize, canonical: bool) -> Vec<String> {
+        seq.windows(k)
+            .filter(|w| w.iter().all(|x| b"ACGT".contains(x)))
+            .map(|w| {
+                let fwd = String::from_utf8(w.to_vec()).unwrap();
+                let rev = w
+                    .iter()
+                    .rev()
+                    .map(|x| match x {
+                        b'A' => 'T',
+                        b'C' => 'G',
+                        b'G' => 'C',
+                        _ => 'A',
+                    })
+                    .collect::<String>();
+                if canonical { fwd.min(rev) } else { fwd }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kmers_skip_other_bases() {
+        assert_eq!(kmers::<u64>(b"ACGTNACG", 3, false), ["ACG", "CGT", "ACG"]);
+        assert_eq!(kmers::<u64>(b"acgu", 3, false), ["ACG", "CGT"]);
+        assert!(kmers::<u64>(b"AC", 3, false).is_empty());
+    }
+
+    #[test]
+    fn canonical_kmers() {
+        assert_eq!(kmers::<u64>(b"GTT", 3, true), ["AAC"]);
+        assert_eq!(kmers::<u64>(b"AAC", 3, true), ["AAC"]);
+        // palindrome
+        assert_eq!(kmers::<u64>(b"ACGT", 4, true), ["ACGT"]);
+    }
+
+    #[test]
+    fn kmers_of_max_size() {
+        let seq = b"ACGGTCATTGACCAGTTACGATCGGATACGTTAGCATCGGACTAGCTAGGATCGATTACGGCATACGGATACCAGT";
+        for canonical in [false, true] {
+            for k in [1, 5, 31, 32] {
+                assert_eq!(
+                    kmers::<u64>(seq, k, canonical),
+                    naive_kmers(seq, k, canonical)
+                );
             }
-            for kmer in seq.windows(kmer_len) {
-                *kmers.entry(kmer.to_vec()).or_insert(0_u64) += 1;
+            for k in [33, 63, 64] {
+                assert_eq!(
+                    kmers::<u128>(seq, k, canonical),
+                    naive_kmers(seq, k, canonical)
+                );
             }
         }
     }
 
-    if header {
-        writer.write_all(b"kmer\tcount\n")?;
-    }
-    for (k, v) in kmers {
-        writer.write_all(&k)?;
-        writer.write_all(format!("\t{}\n", v).as_bytes())?;
-        count += 1;
+    #[test]
+    fn encode_and_decode() {
+        let kmer = encode_kmer::<u64>(b"GATTACA", 7, false).unwrap();
+        assert_eq!(kmer_string(kmer, 7), "GATTACA");
+        assert!(encode_kmer::<u64>(b"GATNACA", 7, false).is_none());
+        assert!(encode_kmer::<u64>(b"GATTACA", 6, false).is_none());
     }
-    writer.flush()?;
-
-    info!("total count kmer type: {}", count);
-    Ok(())
 }
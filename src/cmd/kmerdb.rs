@@ -0,0 +1,508 @@
+use crate::{
+    cmd::kmer::{Kmer, encode_kmer, for_each_kmer, kmer_string},
+    errors::FakitError,
+    report::{Cell, ReportFormat, ReportWriter},
+    utils::{CompressionFormat, file_reader, file_writer, multi_reader, process_records},
+};
+use log::{info, warn};
+use paraseq::{fasta::Reader, fastx::Record};
+use std::{
+    cmp::Ordering,
+    io::{BufRead, Write},
+    path::Path,
+};
+
+// kmer database layout, all integers are little endian:
+// magic "FKDB", version, kmer size, canonical flag, reserved byte, number of kmers as u64,
+// then kmers in sorted order, each packed into (2k+7)/8 bytes followed by its count as a varint
+const KDB_MAGIC: &[u8; 4] = b"FKDB";
+const KDB_VERSION: u8 = 1;
+// entries reserved up front, the number of kmers in header is not trusted before they are read
+const KDB_RESERVE: u64 = 1 << 20;
+
+/// set operation of two kmer databases
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KmerSet {
+    /// kmers in both databases, with the smaller count
+    Intersect,
+    /// kmers of the first database not in the second one
+    Subtract,
+    /// kmers in any database, with counts summed
+    Union,
+}
+
+/// A sorted kmer count table saved by kmer --dump.
+pub struct KmerDb<K> {
+    pub k: usize,
+    pub canonical: bool,
+    pub table: Vec<(K, u64)>,
+}
+
+struct KdbHeader {
+    k: usize,
+    canonical: bool,
+    len: u64,
+}
+
+fn invalid_db<P: AsRef<Path>>(path: P, reason: &str) -> FakitError {
+    FakitError::InvalidKmerDb {
+        path: path.as_ref().to_path_buf(),
+        reason: reason.to_string(),
+    }
+}
+
+// read exactly buf.len() bytes, a short read means the database is truncated
+fn read_db_bytes<P: AsRef<Path>>(
+    reader: &mut dyn BufRead,
+    buf: &mut [u8],
+    path: P,
+) -> Result<(), FakitError> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => invalid_db(path, "unexpected end of file"),
+        _ => FakitError::IoError(e),
+    })
+}
+
+fn read_header<P: AsRef<Path> + Copy>(
+    reader: &mut dyn BufRead,
+    path: P,
+) -> Result<KdbHeader, FakitError> {
+    let mut head = [0u8; 16];
+    read_db_bytes(reader, &mut head, path)?;
+    if &head[..4] != KDB_MAGIC {
+        return Err(invalid_db(path, "not a fakit kmer database"));
+    }
+    if head[4] != KDB_VERSION {
+        return Err(invalid_db(
+            path,
+            &format!("unsupported version {}", head[4]),
+        ));
+    }
+    let k = head[5] as usize;
+    if k == 0 || k > u128::MAX_K {
+        return Err(invalid_db(path, &format!("invalid kmer size {}", k)));
+    }
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&head[8..]);
+    Ok(KdbHeader {
+        k,
+        canonical: head[6] == 1,
+        len: u64::from_le_bytes(len),
+    })
+}
+
+// open a database and read its header, the reader is left at the first kmer
+fn open_db<P: AsRef<Path> + Copy>(
+    path: P,
+) -> Result<(KdbHeader, Box<dyn BufRead + Send>), FakitError> {
+    let mut reader = file_reader(Some(path))?;
+    let header = read_header(&mut reader, path)?;
+    Ok((header, reader))
+}
+
+impl<K: Kmer + Default> KmerDb<K> {
+    fn read_entries<P: AsRef<Path> + Copy>(
+        header: KdbHeader,
+        reader: &mut dyn BufRead,
+        path: P,
+    ) -> Result<Self, FakitError> {
+        let nbytes = (2 * header.k).div_ceil(8);
+        let mut table = Vec::with_capacity(header.len.min(KDB_RESERVE) as usize);
+        let mut kmer = [0u8; 16];
+        for _ in 0..header.len {
+            read_db_bytes(reader, &mut kmer[..nbytes], path)?;
+            let mut count = 0u64;
+            let mut byte = [0u8; 1];
+            for shift in (0..64).step_by(7) {
+                read_db_bytes(reader, &mut byte, path)?;
+                count |= ((byte[0] & 0x7f) as u64) << shift;
+                if byte[0] & 0x80 == 0 {
+                    break;
+                }
+            }
+            let kmer = K::from_u128(u128::from_le_bytes(kmer));
+            // lookup and set operations rely on strictly increasing kmers
+            if table.last().is_some_and(|x: &(K, u64)| x.0 >= kmer) {
+                return Err(invalid_db(path, "kmers are not sorted or not unique"));
+            }
+            table.push((kmer, count));
+        }
+        Ok(KmerDb {
+            k: header.k,
+            canonical: header.canonical,
+            table,
+        })
+    }
+
+    /// Write the database, kmers must be sorted.
+    pub fn write<W: Write + ?Sized>(&self, writer: &mut W) -> Result<(), FakitError> {
+        let nbytes = (2 * self.k).div_ceil(8);
+        writer.write_all(KDB_MAGIC)?;
+        writer.write_all(&[KDB_VERSION, self.k as u8, self.canonical as u8, 0])?;
+        writer.write_all(&(self.table.len() as u64).to_le_bytes())?;
+        for (kmer, count) in self.table.iter() {
+            writer.write_all(&kmer.to_u128().to_le_bytes()[..nbytes])?;
+            let mut count = *count;
+            loop {
+                let byte = (count & 0x7f) as u8;
+                count >>= 7;
+                if count == 0 {
+                    writer.write_all(&[byte])?;
+                    break;
+                }
+                writer.write_all(&[byte | 0x80])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Count of a kmer, 0 if it is not in the database.
+    pub fn get(&self, kmer: K) -> u64 {
+        self.table
+            .binary_search_by_key(&kmer, |x| x.0)
+            .map_or(0, |i| self.table[i].1)
+    }
+
+    // merge two sorted tables
+    fn combine(&self, other: &KmerDb<K>, op: KmerSet) -> Vec<(K, u64)> {
+        let (a, b) = (&self.table, &other.table);
+        let (mut i, mut j) = (0, 0);
+        let mut table = vec![];
+        while i < a.len() || j < b.len() {
+            let ord = match (a.get(i), b.get(j)) {
+                (Some(x), Some(y)) => x.0.cmp(&y.0),
+                (Some(_), None) => Ordering::Less,
+                _ => Ordering::Greater,
+            };
+            match ord {
+                Ordering::Less => {
+                    if op != KmerSet::Intersect {
+                        table.push(a[i]);
+                    }
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    if op == KmerSet::Union {
+                        table.push(b[j]);
+                    }
+                    j += 1;
+                }
+                Ordering::Equal => {
+                    match op {
+                        KmerSet::Intersect => table.push((a[i].0, a[i].1.min(b[j].1))),
+                        KmerSet::Union => table.push((a[i].0, a[i].1.saturating_add(b[j].1))),
+                        KmerSet::Subtract => {}
+                    }
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        table
+    }
+}
+
+/// Write kmer counts into a database file.
+pub fn dump_kmers<K: Kmer + Default>(
+    db: &KmerDb<K>,
+    path: &String,
+    compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
+) -> Result<(), FakitError> {
+    let mut writer = file_writer(Some(path), compression_level, threads, output_compression)?;
+    db.write(&mut writer)?;
+    writer.flush()?;
+    info!("{} kmers saved to database: {}", db.table.len(), path);
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn query_db<K: Kmer + Default>(
+    header: KdbHeader,
+    mut reader: Box<dyn BufRead + Send>,
+    db_path: &String,
+    input: &[String],
+    tag_source: bool,
+    strict: bool,
+    list: Option<&String>,
+    set: Option<(KmerSet, &String)>,
+    dump: Option<&String>,
+    header_line: bool,
+    format: ReportFormat,
+    output: Option<&String>,
+    compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
+) -> Result<(), FakitError> {
+    let mut db = KmerDb::<K>::read_entries(header, &mut reader, db_path)?;
+    info!(
+        "database {}: {} kmers, kmer size: {}, canonical: {}",
+        db_path,
+        db.table.len(),
+        db.k,
+        db.canonical
+    );
+
+    if let Some((op, other_path)) = set {
+        let (other_header, mut other_reader) = open_db(other_path)?;
+        if other_header.k != db.k || other_header.canonical != db.canonical {
+            return Err(FakitError::InvalidArgument(format!(
+                "kmer size or canonical mode of {} differs from {}",
+                other_path, db_path
+            )));
+        }
+        let other = KmerDb::<K>::read_entries(other_header, &mut other_reader, other_path)?;
+        db.table = db.combine(&other, op);
+        info!("{:?} with {}: {} kmers", op, other_path, db.table.len());
+        if let Some(path) = dump {
+            return dump_kmers(&db, path, compression_level, threads, output_compression);
+        }
+    }
+
+    let mut writer = ReportWriter::new(
+        file_writer(output, compression_level, threads, output_compression)?,
+        format,
+    );
+    if let Some(list) = list {
+        // look up kmers listed one per line
+        if header_line {
+            format.write_header(&mut writer, &["kmer", "count"])?;
+        }
+        for line in file_reader(Some(list))?.lines() {
+            let line = line?;
+            let Some(kmer) = line.split_whitespace().next() else {
+                continue;
+            };
+            let count = match encode_kmer::<K>(kmer.as_bytes(), db.k, db.canonical) {
+                Some(x) => Cell::from(db.get(x)),
+                None => {
+                    warn!("invalid kmer of size {}: {}", db.k, kmer);
+                    Cell::Null
+                }
+            };
+            format.write_row(&mut writer, &[("kmer", Cell::from(kmer)), ("count", count)])?;
+        }
+    } else if !input.is_empty() {
+        // kmer coverage of each sequence
+        let columns = ["id", "length", "kmers", "found", "coverage", "mean_count"];
+        if header_line {
+            format.write_header(&mut writer, &columns)?;
+        }
+        let fa_reader = multi_reader(input, tag_source, strict).map(Reader::new)?;
+        let count = process_records(fa_reader, threads, &mut writer, |rec, buf| {
+            let (mut kmers, mut found, mut sum) = (0usize, 0usize, 0u64);
+            for_each_kmer(&rec.seq(), db.k, db.canonical, |kmer: K| {
+                let count = db.get(kmer);
+                kmers += 1;
+                sum += count;
+                if count > 0 {
+                    found += 1;
+                }
+            });
+            let (coverage, mean) = match kmers {
+                0 => (Cell::Null, Cell::Null),
+                n => (
+                    Cell::Float(found as f64 / n as f64, 4),
+                    Cell::Float(sum as f64 / n as f64, 2),
+                ),
+            };
+            let id = rec.id_str().split_whitespace().next().unwrap_or("");
+            let row = [
+                Cell::from(id),
+                Cell::from(rec.seq().len()),
+                Cell::from(kmers),
+                Cell::from(found),
+                coverage,
+                mean,
+            ];
+            format.write_row(buf, &columns.into_iter().zip(row).collect::<Vec<_>>())?;
+            Ok(true)
+        })?;
+        info!("total sequences processed: {}", count);
+    } else {
+        if header_line {
+            format.write_header(&mut writer, &["kmer", "count"])?;
+        }
+        for (kmer, count) in db.table.iter() {
+            let row = [
+                ("kmer", Cell::Str(kmer_string(*kmer, db.k))),
+                ("count", Cell::from(*count)),
+            ];
+            format.write_row(&mut writer, &row)?;
+        }
+    }
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn kmer_query(
+    db_path: &String,
+    input: &[String],
+    tag_source: bool,
+    strict: bool,
+    list: Option<&String>,
+    set: Option<(KmerSet, &String)>,
+    dump: Option<&String>,
+    header_line: bool,
+    format: ReportFormat,
+    output: Option<&String>,
+    compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
+) -> Result<(), FakitError> {
+    if dump.is_some() && set.is_none() {
+        return Err(FakitError::InvalidArgument(
+            "option --dump needs a set operation: --intersect, --subtract or --union".to_string(),
+        ));
+    }
+    let (header, reader) = open_db(db_path)?;
+    if header.k <= u64::MAX_K {
+        query_db::<u64>(
+            header,
+            reader,
+            db_path,
+            input,
+            tag_source,
+            strict,
+            list,
+            set,
+            dump,
+            header_line,
+            format,
+            output,
+            compression_level,
+            threads,
+            output_compression,
+        )
+    } else {
+        query_db::<u128>(
+            header,
+            reader,
+            db_path,
+            input,
+            tag_source,
+            strict,
+            list,
+            set,
+            dump,
+            header_line,
+            format,
+            output,
+            compression_level,
+            threads,
+            output_compression,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_db(buf: &[u8]) -> Result<KmerDb<u64>, FakitError> {
+        let mut reader = buf;
+        let header = read_header(&mut reader, "test.kdb")?;
+        KmerDb::<u64>::read_entries(header, &mut reader, "test.kdb")
+    }
+
+    fn db_of(kmers: &[(&str, u64)]) -> KmerDb<u64> {
+        let table = kmers
+            .iter()
+            .map(|(x, n)| {
+                (
+                    encode_kmer::<u64>(x.as_bytes(), x.len(), false).unwrap(),
+                    *n,
+                )
+            })
+            .collect();
+        KmerDb {
+            k: kmers[0].0.len(),
+            canonical: false,
+            table,
+        }
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let db = db_of(&[("AAAAC", 1), ("ACGTA", 300), ("TTTTT", u64::MAX)]);
+        let mut buf = vec![];
+        db.write(&mut buf).unwrap();
+        let read = read_db(&buf).unwrap();
+        assert_eq!((read.k, read.canonical), (5, false));
+        assert_eq!(read.table, db.table);
+        let kmer = encode_kmer::<u64>(b"ACGTA", 5, false).unwrap();
+        assert_eq!(read.get(kmer), 300);
+        let kmer = encode_kmer::<u64>(b"CCCCC", 5, false).unwrap();
+        assert_eq!(read.get(kmer), 0);
+    }
+
+    #[test]
+    fn invalid_database() {
+        let db = db_of(&[("AAAAC", 1), ("ACGTA", 300)]);
+        let mut buf = vec![];
+        db.write(&mut buf).unwrap();
+        // truncated entries
+        assert!(matches!(
+            read_db(&buf[..buf.len() - 1]),
+            Err(FakitError::InvalidKmerDb { .. })
+        ));
+        // number of kmers in header larger than the file
+        let mut head = b"FKDB\x01\x05\x00\x00".to_vec();
+        head.extend_from_slice(&[0xff; 8]);
+        assert!(matches!(
+            read_db(&head),
+            Err(FakitError::InvalidKmerDb { .. })
+        ));
+        assert!(read_db(b"FKDX").is_err());
+
+        // kmers out of order can't be merged
+        let mut db = db_of(&[("ACGTA", 1), ("AAAAC", 2)]);
+        let mut buf = vec![];
+        db.write(&mut buf).unwrap();
+        assert!(matches!(
+            read_db(&buf),
+            Err(FakitError::InvalidKmerDb { .. })
+        ));
+        db.table[1] = db.table[0];
+        buf.clear();
+        db.write(&mut buf).unwrap();
+        assert!(read_db(&buf).is_err());
+    }
+
+    #[test]
+    fn set_operations() {
+        let a = db_of(&[("AAA", 2), ("CCC", 3)]);
+        let b = db_of(&[("CCC", 1), ("GGG", 4)]);
+        let counts = |table: Vec<(u64, u64)>| {
+            table
+                .into_iter()
+                .map(|(x, n)| (kmer_string(x, 3), n))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            counts(a.combine(&b, KmerSet::Intersect)),
+            [("CCC".to_string(), 1)]
+        );
+        assert_eq!(
+            counts(a.combine(&b, KmerSet::Subtract)),
+            [("AAA".to_string(), 2)]
+        );
+        assert_eq!(
+            counts(a.combine(&b, KmerSet::Union)),
+            [
+                ("AAA".to_string(), 2),
+                ("CCC".to_string(), 4),
+                ("GGG".to_string(), 4)
+            ]
+        );
+        // counts add up without overflow
+        let big = db_of(&[("CCC", u64::MAX)]);
+        assert_eq!(
+            counts(a.combine(&big, KmerSet::Union))[1],
+            ("CCC".to_string(), u64::MAX)
+        );
+    }
+}
@@ -1,12 +1,17 @@
+pub mod alphabet;
+pub mod amplicon;
 pub mod codon;
 pub mod fa2fq;
 pub mod faidx;
 pub mod flatten;
 pub mod grep;
 pub mod kmer;
+pub mod kmerdb;
+pub mod orf;
 pub mod range;
 pub mod rename;
 pub mod reverse;
+pub mod rmdup;
 pub mod search;
 pub mod seq;
 pub mod shuffle;
@@ -19,4 +24,6 @@ pub mod stat;
 pub mod subfa;
 pub mod tail;
 pub mod top;
+pub mod translate;
+pub mod validate;
 pub mod wrap;
@@ -0,0 +1,278 @@
+use crate::{
+    cmd::{
+        codon::CodonTable, reverse::reverse_complement, translate::translate_seq,
+        wrap::write_record,
+    },
+    errors::FakitError,
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader},
+};
+use clap::ValueEnum;
+use log::info;
+use paraseq::{
+    fasta::{Reader, RecordSet},
+    fastx::Record,
+};
+use std::{io::Write, path::Path};
+
+/// codons an orf can start with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StartCodon {
+    /// ATG only
+    Atg,
+    /// all initiation codons of the translation table
+    Table,
+    /// any sense codon, orf runs from stop to stop
+    Any,
+}
+
+/// how to report orfs sharing the same stop codon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Nested {
+    /// only the longest orf from the most upstream start codon
+    Longest,
+    /// an orf from each in-frame start codon
+    All,
+}
+
+struct Orf {
+    // 0-based half-open position on the forward strand
+    start: usize,
+    end: usize,
+    frame: i8,
+    // no stop codon before the sequence end
+    partial: bool,
+    seq: Vec<u8>,
+}
+
+// orfs on one strand, positions are on the strand itself
+fn scan_strand(
+    strand: &[u8],
+    frame_sign: i8,
+    table: &CodonTable,
+    start_codon: StartCodon,
+    nested: Nested,
+    partial: bool,
+    orfs: &mut Vec<(usize, usize, i8, bool)>,
+) {
+    for offset in 0..3 {
+        let frame = frame_sign * (offset as i8 + 1);
+        let mut starts: Vec<usize> = vec![];
+        let mut pos = offset;
+        while pos + 3 <= strand.len() {
+            let codon = &strand[pos..pos + 3];
+            if table.translate(codon) == b'*' {
+                for &start in starts.iter() {
+                    orfs.push((start, pos + 3, frame, false));
+                }
+                starts.clear();
+            } else {
+                let is_start = match start_codon {
+                    StartCodon::Atg => codon.eq_ignore_ascii_case(b"ATG"),
+                    StartCodon::Table => table.is_start(codon),
+                    StartCodon::Any => starts.is_empty(),
+                };
+                if is_start && (starts.is_empty() || nested == Nested::All) {
+                    starts.push(pos);
+                }
+            }
+            pos += 3;
+        }
+        if partial {
+            for &start in starts.iter() {
+                orfs.push((start, pos, frame, true));
+            }
+        }
+    }
+}
+
+fn find_orfs(
+    seq: &[u8],
+    table: &CodonTable,
+    start_codon: StartCodon,
+    nested: Nested,
+    partial: bool,
+    min_len: usize,
+) -> Vec<Orf> {
+    let len = seq.len();
+    let rev = reverse_complement(seq);
+    let mut found = vec![];
+    scan_strand(seq, 1, table, start_codon, nested, partial, &mut found);
+    let forward = found.len();
+    scan_strand(&rev, -1, table, start_codon, nested, partial, &mut found);
+
+    let mut orfs = found
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (start, end, _, _))| end - start >= min_len)
+        .map(|(i, (start, end, frame, partial))| {
+            if i < forward {
+                Orf {
+                    start,
+                    end,
+                    frame,
+                    partial,
+                    seq: seq[start..end].to_vec(),
+                }
+            } else {
+                Orf {
+                    start: len - end,
+                    end: len - start,
+                    frame,
+                    partial,
+                    seq: rev[start..end].to_vec(),
+                }
+            }
+        })
+        .collect::<Vec<Orf>>();
+    orfs.sort_by_key(|x| (x.start, x.end, x.frame));
+    orfs
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn orf_fasta<P: AsRef<Path>>(
+    input: &[P],
+    tag_source: bool,
+    strict: bool,
+    table: u8,
+    min_len: usize,
+    start_codon: StartCodon,
+    nested: Nested,
+    partial: bool,
+    protein: bool,
+    output: Option<&P>,
+    bed: Option<&P>,
+    gff: Option<&P>,
+    line_width: usize,
+    compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
+) -> Result<(), FakitError> {
+    let table = CodonTable::get(table)?;
+    info!("translation table {}: {}", table.id, table.name);
+    info!("start codon: {:?}, nested orf: {:?}", start_codon, nested);
+
+    let mut fa_reader = multi_reader(input, tag_source, strict).map(Reader::new)?;
+    let mut rset = RecordSet::default();
+    let mut writer = file_writer(output, compression_level, threads, output_compression)?;
+    let mut bed_writer = bed
+        .map(|path| file_writer(Some(path), compression_level, threads, output_compression))
+        .transpose()?;
+    let mut gff_writer = match gff {
+        Some(path) => {
+            let mut w = file_writer(Some(path), compression_level, threads, output_compression)?;
+            w.write_all(b"##gff-version 3\n")?;
+            Some(w)
+        }
+        None => None,
+    };
+
+    let mut count = 0usize;
+    let mut nrec = 0usize;
+    while rset.fill(&mut fa_reader)? {
+        for rec in batch_records(&rset, &mut nrec) {
+            let rec = rec?;
+            let name = rec.id_str().split_whitespace().next().unwrap_or("");
+            let orfs = find_orfs(&rec.seq(), table, start_codon, nested, partial, min_len);
+
+            for (i, orf) in orfs.iter().enumerate() {
+                count += 1;
+                let orf_id = format!("{}_orf{}", name, i + 1);
+                let strand = if orf.frame > 0 { '+' } else { '-' };
+                let id = format!(
+                    "{} {}:{}-{}({}) frame={:+} len={}{}",
+                    orf_id,
+                    name,
+                    orf.start + 1,
+                    orf.end,
+                    strand,
+                    orf.frame,
+                    orf.end - orf.start,
+                    if orf.partial { " partial" } else { "" }
+                );
+                if protein {
+                    let aa = translate_seq(&orf.seq, table, true, start_codon != StartCodon::Any);
+                    write_record(&mut writer, id.as_bytes(), &aa, line_width)?;
+                } else {
+                    write_record(&mut writer, id.as_bytes(), &orf.seq, line_width)?;
+                }
+
+                if let Some(w) = bed_writer.as_mut() {
+                    writeln!(
+                        w,
+                        "{}\t{}\t{}\t{}\t0\t{}",
+                        name, orf.start, orf.end, orf_id, strand
+                    )?;
+                }
+                if let Some(w) = gff_writer.as_mut() {
+                    writeln!(
+                        w,
+                        "{}\tfakit\tORF\t{}\t{}\t.\t{}\t0\tID={};frame={:+}{}",
+                        name,
+                        orf.start + 1,
+                        orf.end,
+                        strand,
+                        orf_id,
+                        orf.frame,
+                        if orf.partial { ";partial=true" } else { "" }
+                    )?;
+                }
+            }
+        }
+    }
+    writer.flush()?;
+    if let Some(mut w) = bed_writer {
+        w.flush()?;
+    }
+    if let Some(mut w) = gff_writer {
+        w.flush()?;
+    }
+
+    info!("total orfs found: {}", count);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn orfs(seq: &[u8], nested: Nested, partial: bool, min_len: usize) -> Vec<Orf> {
+        let table = CodonTable::get(1).unwrap();
+        find_orfs(seq, table, StartCodon::Atg, nested, partial, min_len)
+    }
+
+    #[test]
+    fn forward_orf() {
+        let found = orfs(b"CCATGAAATAGCC", Nested::Longest, false, 0);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].start, found[0].end, found[0].frame), (2, 11, 3));
+        assert_eq!(found[0].seq, b"ATGAAATAG");
+        assert!(!found[0].partial);
+        assert!(orfs(b"CCATGAAATAGCC", Nested::Longest, false, 10).is_empty());
+    }
+
+    #[test]
+    fn reverse_orf() {
+        let found = orfs(b"TCAGGGCAT", Nested::Longest, false, 0);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].start, found[0].end, found[0].frame), (0, 9, -1));
+        assert_eq!(found[0].seq, b"ATGCCCTGA");
+    }
+
+    #[test]
+    fn nested_orfs() {
+        let found = orfs(b"ATGATGTAA", Nested::Longest, false, 0);
+        assert_eq!(found.len(), 1);
+        let found = orfs(b"ATGATGTAA", Nested::All, false, 0);
+        let pos = found.iter().map(|x| (x.start, x.end)).collect::<Vec<_>>();
+        assert_eq!(pos, vec![(0, 9), (3, 9)]);
+    }
+
+    #[test]
+    fn partial_orf() {
+        assert!(orfs(b"ATGAAA", Nested::Longest, false, 0).is_empty());
+        let found = orfs(b"ATGAAA", Nested::Longest, true, 0);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].start, found[0].end), (0, 6));
+        assert!(found[0].partial);
+    }
+}
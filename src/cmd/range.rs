@@ -1,31 +1,38 @@
 use crate::{
     cmd::wrap::write_record,
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader},
 };
 use log::info;
 use paraseq::fasta::{Reader, RecordSet};
 use std::path::Path;
 
-pub fn range_fasta<P: AsRef<Path> + Copy>(
-    input: Option<P>,
+#[allow(clippy::too_many_arguments)]
+pub fn range_fasta<P: AsRef<Path>>(
+    input: &[P],
+    tag_source: bool,
+    strict: bool,
     skip: usize,
     take: usize,
-    output: Option<P>,
+    output: Option<&P>,
     line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut fp_reader = file_reader(input).map(Reader::new)?;
+    let mut fp_reader = multi_reader(input, tag_source, strict).map(Reader::new)?;
     let mut rset = RecordSet::default();
     info!("skip first {} records", skip);
     info!("get {} records", take);
 
-    let mut fp_writer = file_writer(output, compression_level)?;
+    let mut fp_writer = file_writer(output, compression_level, threads, output_compression)?;
     let mut skipped = 0usize;
     let mut taken = 0usize;
 
+    let mut nrec = 0usize;
     'outer: while rset.fill(&mut fp_reader)? {
-        for rec in rset.iter().map_while(Result::ok) {
+        for rec in batch_records(&rset, &mut nrec) {
+            let rec = rec?;
             if skipped < skip {
                 skipped += 1;
                 continue;
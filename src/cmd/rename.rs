@@ -1,7 +1,7 @@
 use crate::{
     cmd::wrap::write_record,
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader},
 };
 use log::info;
 use paraseq::{
@@ -10,21 +10,28 @@ use paraseq::{
 };
 use std::path::Path;
 
-pub fn rename_fa<P: AsRef<Path> + Copy>(
-    input: Option<P>,
+#[allow(clippy::too_many_arguments)]
+pub fn rename_fa<P: AsRef<Path>>(
+    input: &[P],
+    tag_source: bool,
+    strict: bool,
     keep: bool,
     prefix: Option<String>,
-    output: Option<P>,
+    output: Option<&P>,
     line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut fp = file_reader(input).map(Reader::new)?;
+    let mut fp = multi_reader(input, tag_source, strict).map(Reader::new)?;
     let mut rset = RecordSet::default();
-    let mut writer = file_writer(output, compression_level)?;
+    let mut writer = file_writer(output, compression_level, threads, output_compression)?;
     let mut n = 0usize;
 
+    let mut nrec = 0usize;
     while rset.fill(&mut fp)? {
-        for rec in rset.iter().map_while(Result::ok) {
+        for rec in batch_records(&rset, &mut nrec) {
+            let rec = rec?;
             n += 1;
             if let Some(pre) = &prefix {
                 let newid = format!("{}{}", pre, n);
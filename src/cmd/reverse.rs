@@ -1,53 +1,138 @@
 use crate::{
     cmd::wrap::write_record,
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    utils::{CompressionFormat, file_writer, multi_reader, process_records},
 };
 use log::info;
-use paraseq::fasta::{Reader, RecordSet};
+use paraseq::fasta::Reader;
 use std::path::Path;
 
-pub fn reverse_comp_seq<P: AsRef<Path> + Copy>(
-    input: Option<P>,
-    out: Option<P>,
+#[allow(clippy::too_many_arguments)]
+pub fn reverse_comp_seq<P: AsRef<Path>>(
+    input: &[P],
+    tag_source: bool,
+    strict: bool,
+    out: Option<&P>,
     rev: bool,
+    comp: bool,
+    rna: bool,
+    tag: Option<&str>,
     line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut fa_reader = file_reader(input).map(Reader::new)?;
-    let mut rset = RecordSet::default();
-    let mut out_writer = file_writer(out, compression_level)?;
-    let mut conter = 0usize;
-
-    while rset.fill(&mut fa_reader)? {
-        for rec in rset.iter().map_while(Result::ok) {
-            let seq = rec.seq();
-            let new_seq = if rev {
-                seq.iter().copied().rev().collect::<Vec<u8>>()
-            } else {
-                seq.iter()
-                    .rev()
-                    .map(|x| match x {
-                        b'A' => b'T',
-                        b'T' => b'A',
-                        b'G' => b'C',
-                        b'C' => b'G',
-                        b'N' => b'N',
-                        b'a' => b't',
-                        b't' => b'a',
-                        b'g' => b'c',
-                        b'c' => b'g',
-                        b'n' => b'n',
-                        _ => b'N',
-                    })
-                    .collect::<Vec<u8>>()
-            };
-            write_record(&mut out_writer, rec.id(), &new_seq, line_width)?;
-            conter += 1;
-        }
+    if rev && comp {
+        return Err(FakitError::InvalidArgument(
+            "only one of the flags -r (--reverse), -c (--complement) is allowed".to_string(),
+        ));
     }
+    let tag = tag.map(|x| match x {
+        "" if rev => "/rev",
+        "" if comp => "/comp",
+        "" => "/rc",
+        x => x,
+    });
+    let fa_reader = multi_reader(input, tag_source, strict).map(Reader::new)?;
+    let mut out_writer = file_writer(out, compression_level, threads, output_compression)?;
+
+    let conter = process_records(fa_reader, threads, &mut out_writer, |rec, buf| {
+        let seq = rec.seq();
+        let new_seq = if rev {
+            seq.iter().copied().rev().collect::<Vec<u8>>()
+        } else if comp {
+            seq.iter()
+                .map(|&x| complement_base(x, rna))
+                .collect::<Vec<u8>>()
+        } else {
+            seq.iter()
+                .rev()
+                .map(|&x| complement_base(x, rna))
+                .collect::<Vec<u8>>()
+        };
+        match tag {
+            Some(tag) => write_record(buf, &tag_id(rec.id(), tag), &new_seq, line_width)?,
+            None => write_record(buf, rec.id(), &new_seq, line_width)?,
+        }
+        Ok(true)
+    })?;
     out_writer.flush()?;
 
     info!("total sequences processed count: {}", conter);
     Ok(())
 }
+
+// the tag goes right after the sequence id, before the whitespace and description
+fn tag_id(id: &[u8], tag: &str) -> Vec<u8> {
+    let end = id
+        .iter()
+        .position(|x| x.is_ascii_whitespace())
+        .unwrap_or(id.len());
+    [&id[..end], tag.as_bytes(), &id[end..]].concat()
+}
+
+/// Complement a nucleotide with IUPAC ambiguity code, letter case is kept, gaps are unchanged,
+/// A is complemented to U in rna mode.
+pub fn complement_base(nt: u8, rna: bool) -> u8 {
+    let comp = match nt.to_ascii_uppercase() {
+        b'A' if rna => b'U',
+        b'A' => b'T',
+        b'T' | b'U' => b'A',
+        b'G' => b'C',
+        b'C' => b'G',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'K' => b'M',
+        b'M' => b'K',
+        b'S' => b'S',
+        b'W' => b'W',
+        b'B' => b'V',
+        b'V' => b'B',
+        b'D' => b'H',
+        b'H' => b'D',
+        b'N' => b'N',
+        b'-' | b'.' | b'*' => return nt,
+        _ => b'N',
+    };
+    if nt.is_ascii_lowercase() {
+        comp.to_ascii_lowercase()
+    } else {
+        comp
+    }
+}
+
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|&x| complement_base(x, false))
+        .collect::<Vec<u8>>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complement_iupac() {
+        let comp = |seq: &[u8], rna: bool| {
+            seq.iter()
+                .map(|&x| complement_base(x, rna))
+                .collect::<Vec<u8>>()
+        };
+        assert_eq!(comp(b"ACGTRYKMSWBVDHN", false), b"TGCAYRMKSWVBHDN");
+        // case is kept, gaps and stops are unchanged, unknown letters become N
+        assert_eq!(comp(b"acgtRy-.*", false), b"tgcaYr-.*");
+        assert_eq!(comp(b"XzE", false), b"NnN");
+        // U is complemented to A, and A to U only in rna mode
+        assert_eq!(comp(b"ACGUu", false), b"TGCAa");
+        assert_eq!(comp(b"ACGUa", true), b"UGCAu");
+        assert_eq!(reverse_complement(b"AACGTN-r"), b"y-NACGTT");
+    }
+
+    #[test]
+    fn tag_before_description() {
+        assert_eq!(tag_id(b"seq1 some desc", "/rc"), b"seq1/rc some desc");
+        assert_eq!(tag_id(b"seq1\tsome desc", "/rc"), b"seq1/rc\tsome desc");
+        assert_eq!(tag_id(b"seq1", "_rev"), b"seq1_rev");
+    }
+}
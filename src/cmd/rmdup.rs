@@ -0,0 +1,218 @@
+use crate::{
+    cmd::{reverse::reverse_complement, wrap::write_record},
+    errors::FakitError,
+    report::{Cell, ReportFormat, ReportWriter},
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader},
+};
+use clap::ValueEnum;
+use log::info;
+use paraseq::{
+    fasta::{Reader, RecordSet},
+    fastx::Record,
+};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, io::Write, path::Path};
+
+/// key to find duplicate records by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DupKey {
+    /// full header, id and description
+    Name,
+    /// sequence id, the first word of header
+    Id,
+    /// sequence, case insensitive
+    Seq,
+}
+
+// keys seen so far mapped to their group index, digests keep 128 bits of sha256
+enum Seen {
+    Full(HashMap<Vec<u8>, usize>),
+    Digest(HashMap<[u8; 16], usize>),
+}
+
+impl Seen {
+    // group index of a seen key, or None after inserting a new key as group next
+    fn check(&mut self, key: Vec<u8>, next: usize) -> Option<usize> {
+        match self {
+            Seen::Full(map) => match map.get(&key) {
+                Some(x) => Some(*x),
+                None => {
+                    map.insert(key, next);
+                    None
+                }
+            },
+            Seen::Digest(map) => {
+                let mut digest = [0u8; 16];
+                digest.copy_from_slice(&Sha256::digest(&key)[..16]);
+                match map.get(&digest) {
+                    Some(x) => Some(*x),
+                    None => {
+                        map.insert(digest, next);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Seen::Full(map) => map.len(),
+            Seen::Digest(map) => map.len(),
+        }
+    }
+}
+
+// key of a record, a canonical sequence key is the smaller one of both strands
+fn record_key(header: &[u8], seq: &[u8], by: DupKey, canonical: bool) -> Vec<u8> {
+    match by {
+        DupKey::Name => header.to_vec(),
+        DupKey::Id => header
+            .split(|x| x.is_ascii_whitespace())
+            .next()
+            .unwrap_or_default()
+            .to_vec(),
+        DupKey::Seq => {
+            let seq = seq.to_ascii_uppercase();
+            if canonical {
+                seq.clone().min(reverse_complement(&seq))
+            } else {
+                seq
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn rmdup_fasta<P: AsRef<Path>>(
+    file: &[P],
+    tag_source: bool,
+    strict: bool,
+    by: DupKey,
+    canonical: bool,
+    digest: bool,
+    out: Option<&P>,
+    removed: Option<&P>,
+    groups: Option<&P>,
+    header: bool,
+    format: ReportFormat,
+    line_width: usize,
+    compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
+) -> Result<(), FakitError> {
+    if canonical && by != DupKey::Seq {
+        return Err(FakitError::InvalidArgument(
+            "option -C (--canonical) only works with sequence key: -k seq".to_string(),
+        ));
+    }
+    info!(
+        "remove duplicates by {:?}, canonical: {}, digest: {}",
+        by, canonical, digest
+    );
+    let mut seen = if digest {
+        Seen::Digest(HashMap::new())
+    } else {
+        Seen::Full(HashMap::new())
+    };
+    // first record id of each group and ids of its duplicates, only kept for the groups file
+    let mut dup_groups: Vec<(String, Vec<String>)> = vec![];
+
+    let mut fa_reader = multi_reader(file, tag_source, strict).map(Reader::new)?;
+    let mut rset = RecordSet::default();
+    let mut writer = file_writer(out, compression_level, threads, output_compression)?;
+    let mut removed_writer = removed
+        .map(|path| file_writer(Some(path), compression_level, threads, output_compression))
+        .transpose()?;
+
+    let (mut nrec, mut ndup) = (0usize, 0usize);
+    while rset.fill(&mut fa_reader)? {
+        for rec in batch_records(&rset, &mut nrec) {
+            let rec = rec?;
+            let seq = rec.seq();
+            let key = record_key(rec.id(), &seq, by, canonical);
+            let id = rec.id_str().split_whitespace().next().unwrap_or("");
+            match seen.check(key, dup_groups.len()) {
+                None => {
+                    if groups.is_some() {
+                        dup_groups.push((id.to_string(), vec![]));
+                    }
+                    write_record(&mut writer, rec.id(), &seq, line_width)?;
+                }
+                Some(i) => {
+                    ndup += 1;
+                    if let Some(group) = dup_groups.get_mut(i) {
+                        group.1.push(id.to_string());
+                    }
+                    if let Some(w) = removed_writer.as_mut() {
+                        write_record(w, rec.id(), &seq, line_width)?;
+                    }
+                }
+            }
+        }
+    }
+    writer.flush()?;
+    if let Some(mut w) = removed_writer {
+        w.flush()?;
+    }
+
+    if let Some(path) = groups {
+        let mut w = ReportWriter::new(
+            file_writer(Some(path), compression_level, threads, output_compression)?,
+            format,
+        );
+        let columns = ["kept", "duplicates", "removed"];
+        if header {
+            format.write_header(&mut w, &columns)?;
+        }
+        for (kept, dups) in dup_groups.iter().filter(|x| !x.1.is_empty()) {
+            let values = [
+                Cell::from(kept.as_str()),
+                Cell::from(dups.len()),
+                Cell::Str(dups.join(",")),
+            ];
+            format.write_row(&mut w, &columns.iter().zip(values).collect::<Vec<_>>())?;
+        }
+        w.finish()?;
+    }
+
+    info!(
+        "total records: {}, unique: {}, duplicates removed: {}",
+        nrec,
+        seen.len(),
+        ndup
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_keys() {
+        let header = b"seq1 sample=a";
+        assert_eq!(record_key(header, b"acgt", DupKey::Name, false), header);
+        assert_eq!(record_key(header, b"acgt", DupKey::Id, false), b"seq1");
+        assert_eq!(record_key(b"", b"acgt", DupKey::Id, false), b"");
+        assert_eq!(record_key(header, b"aCgTT", DupKey::Seq, false), b"ACGTT");
+    }
+
+    #[test]
+    fn canonical_keys() {
+        // both strands of a sequence give the same key, the smaller one
+        assert_eq!(record_key(b"a", b"TTGCA", DupKey::Seq, true), b"TGCAA");
+        assert_eq!(record_key(b"b", b"tgcaa", DupKey::Seq, true), b"TGCAA");
+        assert_eq!(record_key(b"c", b"TTGCA", DupKey::Seq, false), b"TTGCA");
+    }
+
+    #[test]
+    fn seen_groups() {
+        for mut seen in [Seen::Full(HashMap::new()), Seen::Digest(HashMap::new())] {
+            assert_eq!(seen.check(b"ACGT".to_vec(), 0), None);
+            assert_eq!(seen.check(b"TTTT".to_vec(), 1), None);
+            assert_eq!(seen.check(b"ACGT".to_vec(), 2), Some(0));
+            assert_eq!(seen.len(), 2);
+        }
+    }
+}
@@ -1,64 +1,489 @@
 use crate::{
+    cmd::{codon::base_mask, reverse::reverse_complement},
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    report::{Cell, ReportFormat, ReportWriter},
+    utils::{CompressionFormat, file_writer, multi_reader, process_records},
 };
 use log::info;
-use paraseq::{
-    fasta::{Reader, RecordSet},
-    fastx::Record,
-};
-use regex::RegexBuilder;
-use std::path::Path;
+use paraseq::{fasta::Reader, fastx::Record};
+use regex::{Regex, RegexBuilder};
+use std::{io::Write, path::Path};
+
+/// A motif hit, position is 0-based half-open on the forward strand.
+pub struct MotifHit {
+    pub start: usize,
+    pub end: usize,
+    // number of mismatches or edits
+    pub diff: usize,
+    pub strand: char,
+}
+
+/// An IUPAC nucleotide motif, matched on both strands.
+pub struct Motif {
+    pub name: String,
+    forward: Vec<u8>,
+    reverse: Vec<u8>,
+}
+
+// complement of a base mask: T<->A, C<->G
+fn complement_mask(mask: u8) -> u8 {
+    ((mask & 0b0011) << 2) | ((mask & 0b1100) >> 2)
+}
+
+// a sequence base matches a motif base if all bases it stands for are allowed by the motif,
+// so N in sequence only matches N in motif
+fn base_match(motif: u8, nt: u8) -> bool {
+    let mask = base_mask(nt);
+    mask != 0 && mask & !motif == 0
+}
+
+// hits with up to max_diff mismatches starting at each position
+fn scan_mismatch(motif: &[u8], seq: &[u8], max_diff: usize) -> Vec<(usize, usize, usize)> {
+    let mut hits = vec![];
+    if seq.len() < motif.len() {
+        return hits;
+    }
+    for start in 0..=seq.len() - motif.len() {
+        let mut diff = 0;
+        for (m, nt) in motif.iter().zip(&seq[start..]) {
+            if !base_match(*m, *nt) {
+                diff += 1;
+                if diff > max_diff {
+                    break;
+                }
+            }
+        }
+        if diff <= max_diff {
+            hits.push((start, start + motif.len(), diff));
+        }
+    }
+    hits
+}
+
+// hits with up to max_diff edits in one semi-global dp pass (Sellers): a column holds the edit
+// distance of each motif prefix against the best text ending at the current base, with the start
+// of that alignment. Overlapping hits are one locus, the hit with fewest edits is kept.
+fn scan_edit(motif: &[u8], seq: &[u8], max_diff: usize) -> Vec<(usize, usize, usize)> {
+    let m = motif.len();
+    let mut col = (0..=m).map(|r| (r, 0)).collect::<Vec<(usize, usize)>>();
+    let mut hits = vec![];
+    // best hit of the current locus: start, end, edits
+    let mut locus: Option<(usize, usize, usize)> = None;
+    for (j, nt) in seq.iter().enumerate() {
+        // an alignment can start at any base for free
+        let mut diag = col[0];
+        col[0] = (0, j + 1);
+        for r in 1..=m {
+            let cost = if base_match(motif[r - 1], *nt) { 0 } else { 1 };
+            let left = col[r];
+            let mut best = (diag.0 + cost, diag.1);
+            if col[r - 1].0 + 1 < best.0 {
+                best = (col[r - 1].0 + 1, col[r - 1].1);
+            }
+            if left.0 + 1 < best.0 {
+                best = (left.0 + 1, left.1);
+            }
+            diag = left;
+            col[r] = best;
+        }
+
+        let (diff, start) = col[m];
+        if diff > max_diff {
+            continue;
+        }
+        match locus {
+            // a hit not overlapping the locus starts a new one
+            Some(hit) if start >= hit.1 => {
+                hits.push(hit);
+                locus = Some((start, j + 1, diff));
+            }
+            Some(hit) if hit.2 <= diff => {}
+            _ => locus = Some((start, j + 1, diff)),
+        }
+    }
+    hits.extend(locus);
+    hits.sort_unstable();
+    hits
+}
+
+impl Motif {
+    pub fn new(motif: &str) -> Result<Self, FakitError> {
+        let forward = motif.bytes().map(base_mask).collect::<Vec<u8>>();
+        if forward.is_empty() || forward.contains(&0) {
+            return Err(FakitError::InvalidArgument(format!(
+                "invalid IUPAC nucleotide motif: {}",
+                motif
+            )));
+        }
+        let reverse = forward.iter().rev().map(|x| complement_mask(*x)).collect();
+        Ok(Motif {
+            name: motif.to_string(),
+            forward,
+            reverse,
+        })
+    }
+
+    /// Overlapping hits on one strand with up to max_diff mismatches, or edits if edit is true,
+    /// the reverse strand is searched with the reverse complement of the motif.
+    pub fn find_strand(
+        &self,
+        seq: &[u8],
+        max_diff: usize,
+        edit: bool,
+        reverse: bool,
+    ) -> Vec<MotifHit> {
+        let motif = if reverse {
+            &self.reverse
+        } else {
+            &self.forward
+        };
+        let hits = if edit {
+            scan_edit(motif, seq, max_diff)
+        } else {
+            scan_mismatch(motif, seq, max_diff)
+        };
+        hits.into_iter()
+            .map(|(start, end, diff)| MotifHit {
+                start,
+                end,
+                diff,
+                strand: if reverse { '-' } else { '+' },
+            })
+            .collect()
+    }
+
+    /// Returns true if the motif matches exactly on either strand.
+    pub fn is_match(&self, seq: &[u8]) -> bool {
+        seq.windows(self.forward.len()).any(|window| {
+            [&self.forward, &self.reverse]
+                .iter()
+                .any(|motif| motif.iter().zip(window).all(|(m, nt)| base_match(*m, *nt)))
+        })
+    }
+
+    /// Find hits on both strands, hits of a palindromic motif are only reported on the forward strand.
+    pub fn find(
+        &self,
+        seq: &[u8],
+        max_diff: usize,
+        edit: bool,
+        forward_only: bool,
+    ) -> Vec<MotifHit> {
+        let mut hits = self.find_strand(seq, max_diff, edit, false);
+        if !forward_only && self.reverse != self.forward {
+            hits.extend(self.find_strand(seq, max_diff, edit, true));
+            hits.sort_by_key(|x| (x.start, x.end));
+        }
+        hits
+    }
+}
+
+// split a regex into its top level alternatives
+fn alternatives(pat: &str) -> Vec<String> {
+    let mut alts = vec![];
+    let (mut depth, mut class, mut escape) = (0i32, false, false);
+    let mut cur = String::new();
+    for c in pat.chars() {
+        match c {
+            _ if escape => escape = false,
+            '\\' => escape = true,
+            '[' => class = true,
+            ']' => class = false,
+            '(' if !class => depth += 1,
+            ')' if !class => depth -= 1,
+            '|' if !class && depth == 0 => {
+                alts.push(std::mem::take(&mut cur));
+                continue;
+            }
+            _ => {}
+        }
+        cur.push(c);
+    }
+    alts.push(cur);
+    alts
+}
+
+#[allow(clippy::too_many_arguments)]
+fn search_motif<P: AsRef<Path>>(
+    fp: Reader<Box<dyn std::io::BufRead + Send>>,
+    out: Option<&P>,
+    pat: &str,
+    header: bool,
+    keep: bool,
+    forward_only: bool,
+    max_diff: usize,
+    edit: bool,
+    format: ReportFormat,
+    compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
+) -> Result<(), FakitError> {
+    let motifs = pat
+        .split('|')
+        .map(Motif::new)
+        .collect::<Result<Vec<Motif>, FakitError>>()?;
+    let diff_name = if edit { "edits" } else { "mismatches" };
+    // a motif with as many edits as its bases matches anywhere, even with an empty hit
+    if let Some(motif) = motifs.iter().find(|x| x.forward.len() <= max_diff) {
+        return Err(FakitError::InvalidArgument(format!(
+            "max {} {} must be less than the length of motif: {}",
+            diff_name, max_diff, motif.name
+        )));
+    }
+    info!("motif: {}, max {}: {}", pat, diff_name, max_diff);
+    let mut writer = ReportWriter::new(
+        file_writer(out, compression_level, threads, output_compression)?,
+        format,
+    );
+    // bed6 with the matched sequence, 0-based half-open as named in bed, unlike 1-based regex hits
+    let columns = [
+        "chrom",
+        "chromStart",
+        "chromEnd",
+        "name",
+        "score",
+        "strand",
+        "sequence",
+    ];
+    // a bed header line is a comment
+    if header {
+        if format == ReportFormat::Tsv {
+            writer.write_all(b"#")?;
+        }
+        format.write_header(&mut writer, &columns)?;
+    }
+
+    process_records(fp, threads, &mut writer, |rec, buf| {
+        let seq = rec.seq();
+        let name = if keep {
+            rec.id_str()
+        } else {
+            rec.id_str().split_whitespace().next().unwrap_or("")
+        };
+        for motif in motifs.iter() {
+            for hit in motif.find(&seq, max_diff, edit, forward_only) {
+                let matched = match hit.strand {
+                    '+' => seq[hit.start..hit.end].to_vec(),
+                    _ => reverse_complement(&seq[hit.start..hit.end]),
+                };
+                let values = [
+                    Cell::from(name),
+                    Cell::from(hit.start),
+                    Cell::from(hit.end),
+                    Cell::from(motif.name.as_str()),
+                    Cell::from(hit.diff),
+                    Cell::Str(hit.strand.to_string()),
+                    Cell::Str(String::from_utf8_lossy(&matched).to_string()),
+                ];
+                let row = columns.iter().zip(values).collect::<Vec<_>>();
+                format.write_row(buf, &row)?;
+            }
+        }
+        Ok(true)
+    })?;
+    writer.finish()?;
 
-pub fn search_fa<P: AsRef<Path> + Copy>(
-    file: Option<P>,
-    out: Option<P>,
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn search_fa<P: AsRef<Path>>(
+    file: &[P],
+    tag_source: bool,
+    strict: bool,
+    out: Option<&P>,
     pat: &str,
     ig: bool,
     header: bool,
     keep: bool,
+    motif: bool,
+    forward_only: bool,
+    max_diff: usize,
+    edit: bool,
+    format: ReportFormat,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut fp = file_reader(file).map(Reader::new)?;
-    let mut rset = RecordSet::default();
+    let fp = multi_reader(file, tag_source, strict).map(Reader::new)?;
+    if motif {
+        return search_motif(
+            fp,
+            out,
+            pat,
+            header,
+            keep,
+            forward_only,
+            max_diff,
+            edit,
+            format,
+            compression_level,
+            threads,
+            output_compression,
+        );
+    }
 
     info!("regex pattern is: {}", pat);
     let re = RegexBuilder::new(pat)
         .case_insensitive(ig)
         .unicode(true)
         .build()?;
-    let mut writer = file_writer(out, compression_level)?;
+    // each alternative of the pattern, to report which one matched
+    let alts = alternatives(pat)
+        .into_iter()
+        .map(|alt| {
+            RegexBuilder::new(&format!("^(?:{})$", alt))
+                .case_insensitive(ig)
+                .build()
+                .map(|re| (alt, re))
+        })
+        .collect::<Result<Vec<(String, Regex)>, _>>()
+        .unwrap_or_default();
+    let mut writer = ReportWriter::new(
+        file_writer(out, compression_level, threads, output_compression)?,
+        format,
+    );
+    let columns = [
+        "sequence_name",
+        "start",
+        "end",
+        "pattern",
+        "length",
+        "sequence",
+    ];
     if header {
-        writer.write_all(b"sequence_name\tstart\tend\tpattern\tlength\tsequence\n")?;
-    }
-
-    while rset.fill(&mut fp)? {
-        for rec in rset.iter().map_while(Result::ok) {
-            let seq = rec.seq_str();
-            let result = re.captures_iter(&seq);
-            for ret in result {
-                let group = ret.len();
-                for i in 0..group {
-                    if let Some(x) = ret.get(i) {
-                        if keep {
-                            writer.write_all(rec.id())?;
-                        } else {
-                            writer.write_all(
-                                rec.id_str().split_whitespace().next().unwrap().as_bytes(),
-                            )?;
-                        }
-                        writer
-                            .write_all(format!("\t{}\t{}\t", x.start() + 1, x.end()).as_bytes())?;
-                        writer.write_all(pat.as_bytes())?;
-                        writer.write_all(format!("\t{}\t", x.end() - x.start()).as_bytes())?;
-                        writer.write_all(x.as_str().as_bytes())?;
-                        writer.write_all(b"\n")?;
-                    }
+        format.write_header(&mut writer, &columns)?;
+    }
+
+    process_records(fp, threads, &mut writer, |rec, buf| {
+        let seq = rec.seq_str();
+        let result = re.captures_iter(&seq);
+        for ret in result {
+            let group = ret.len();
+            for i in 0..group {
+                if let Some(x) = ret.get(i) {
+                    let name = if keep {
+                        rec.id_str()
+                    } else {
+                        rec.id_str().split_whitespace().next().unwrap_or("")
+                    };
+                    let alt = alts
+                        .iter()
+                        .find(|(_, re)| re.is_match(x.as_str()))
+                        .map_or(pat, |(alt, _)| alt.as_str());
+                    let values = [
+                        Cell::from(name),
+                        Cell::from(x.start() + 1),
+                        Cell::from(x.end()),
+                        Cell::from(alt),
+                        Cell::from(x.end() - x.start()),
+                        Cell::from(x.as_str()),
+                    ];
+                    let row = columns.iter().zip(values).collect::<Vec<_>>();
+                    format.write_row(buf, &row)?;
                 }
             }
         }
-    }
-    writer.flush()?;
+        Ok(true)
+    })?;
+    writer.finish()?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hits(
+        motif: &str,
+        seq: &[u8],
+        max_diff: usize,
+        edit: bool,
+    ) -> Vec<(usize, usize, usize, char)> {
+        Motif::new(motif)
+            .unwrap()
+            .find(seq, max_diff, edit, false)
+            .into_iter()
+            .map(|x| (x.start, x.end, x.diff, x.strand))
+            .collect()
+    }
+
+    #[test]
+    fn invalid_motif() {
+        assert!(Motif::new("GAXTC").is_err());
+        assert!(Motif::new("").is_err());
+    }
+
+    #[test]
+    fn degenerate_motif_both_strands() {
+        // GANTC is palindromic, hits are only on the forward strand
+        assert_eq!(
+            hits("GANTC", b"GAATCaGACTC", 0, false),
+            [(0, 5, 0, '+'), (6, 11, 0, '+')]
+        );
+        // reverse complement of CCRT is AYGG
+        assert_eq!(hits("CCRT", b"AAACCGTTT", 0, false), [(3, 7, 0, '+')]);
+        assert_eq!(hits("CCRT", b"AAAACGGTTT", 0, false), [(3, 7, 0, '-')]);
+        // N in sequence only matches N in motif
+        assert!(hits("CCRT", b"CCNT", 0, false).is_empty());
+        assert_eq!(hits("CCNT", b"CCNT", 0, false), [(0, 4, 0, '+')]);
+    }
+
+    #[test]
+    fn mismatch_hits() {
+        assert_eq!(hits("ACGTTT", b"GGACGATTGG", 1, false), [(2, 8, 1, '+')]);
+        assert!(hits("ACGTTT", b"GGACGATTGG", 0, false).is_empty());
+    }
+
+    #[test]
+    fn edit_hits() {
+        let motif = Motif::new("ACGTACGT").unwrap().forward;
+        // exact match is one hit, not also the hits with one edit next to it
+        assert_eq!(scan_edit(&motif, b"TTACGTACGTTT", 1), [(2, 10, 0)]);
+        // deletion and insertion in sequence
+        assert_eq!(scan_edit(&motif, b"GGGACGTCGTGGG", 1), [(3, 10, 1)]);
+        assert_eq!(scan_edit(&motif, b"GGGACGTTACGTGGG", 1), [(3, 12, 1)]);
+        // two separate loci
+        assert_eq!(
+            scan_edit(&motif, b"ACGTACGTGGGGGGGGACGTACGT", 0),
+            [(0, 8, 0), (16, 24, 0)]
+        );
+        assert!(scan_edit(&motif, b"GGGACGTCGTGGG", 0).is_empty());
+    }
+
+    #[test]
+    fn edit_hits_minus_strand() {
+        // TCAAGCTT with one base deleted, reverse complement of the motif
+        let found = hits("AAGCTTGA", b"CCCTCAAGTTCCC", 1, true);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].2, found[0].3), (1, '-'));
+    }
+
+    #[test]
+    fn max_edits_below_motif_length() {
+        let reader =
+            || Reader::new(Box::new(&b">s\nACGT\n"[..]) as Box<dyn std::io::BufRead + Send>);
+        for (max_diff, edit) in [(3, true), (4, false)] {
+            let res = search_motif::<&str>(
+                reader(),
+                None,
+                "ACGTA|ACG",
+                false,
+                false,
+                false,
+                max_diff,
+                edit,
+                ReportFormat::Tsv,
+                6,
+                1,
+                None,
+            );
+            assert!(matches!(res, Err(FakitError::InvalidArgument(_))));
+        }
+        // the most edits allowed still give hits of at least one base
+        let motif = Motif::new("ACGT").unwrap().forward;
+        let found = scan_edit(&motif, b"TTTTGGGG", 3);
+        assert!(!found.is_empty() && found.iter().all(|x| x.1 > x.0));
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{
     cmd::wrap::write_record,
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader},
 };
 use log::info;
 use paraseq::{
@@ -12,23 +12,30 @@ use rand::prelude::*;
 use rand_pcg::Pcg64;
 use std::{collections::HashMap, path::Path};
 
-pub fn shuffle_fasta<P: AsRef<Path> + Copy>(
-    file: Option<P>,
+#[allow(clippy::too_many_arguments)]
+pub fn shuffle_fasta<P: AsRef<Path>>(
+    file: &[P],
+    tag_source: bool,
+    strict: bool,
     seed: u64,
-    out: Option<P>,
+    out: Option<&P>,
     line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
     info!("rand seed: {}", seed);
     let mut rng = Pcg64::seed_from_u64(seed);
 
-    let mut fa_reader = file_reader(file).map(Reader::new)?;
+    let mut fa_reader = multi_reader(file, tag_source, strict).map(Reader::new)?;
     let mut rset = RecordSet::default();
     let mut reads_map = HashMap::new();
     let mut index = 0usize;
 
+    let mut nrec = 0usize;
     while rset.fill(&mut fa_reader)? {
-        for rec in rset.iter().map_while(Result::ok) {
+        for rec in batch_records(&rset, &mut nrec) {
+            let rec = rec?;
             reads_map.insert(
                 index,
                 vec![rec.id().to_owned(), rec.seq_str().as_bytes().to_owned()],
@@ -42,7 +49,7 @@ pub fn shuffle_fasta<P: AsRef<Path> + Copy>(
     shuffled_indices.shuffle(&mut rng);
     info!("shuffle done, start write to output ...");
 
-    let mut writer = file_writer(out, compression_level)?;
+    let mut writer = file_writer(out, compression_level, threads, output_compression)?;
     for idx in shuffled_indices {
         if let Some(reads) = reads_map.get(&idx) {
             write_record(&mut writer, reads[0].as_slice(), &reads[1], line_width)?;
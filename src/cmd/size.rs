@@ -1,88 +1,105 @@
 use crate::{
+    cmd::alphabet::{
+        AMINO_ACIDS, SeqType, detect_seq_type, isoelectric_point, molecular_weight, residue_counts,
+    },
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    report::{Cell, ReportFormat, ReportWriter},
+    utils::{CompressionFormat, file_writer, multi_reader, process_records},
 };
 use log::{info, warn};
-use paraseq::{
-    fasta::{Reader, RecordSet},
-    fastx::Record,
-};
+use paraseq::{fasta::Reader, fastx::Record};
 use std::path::Path;
 
-pub fn size_fasta<P: AsRef<Path> + Copy>(
-    input: Option<P>,
+#[allow(clippy::too_many_arguments)]
+pub fn size_fasta<P: AsRef<Path>>(
+    input: &[P],
+    tag_source: bool,
+    strict: bool,
     all: bool,
+    seq_type: SeqType,
     keep: bool,
-    noehader: bool,
-    output: Option<P>,
+    header: bool,
+    format: ReportFormat,
+    output: Option<&P>,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut fa_reader = file_reader(input).map(Reader::new)?;
-    let mut rset = RecordSet::default();
+    let (seq_type, reader) = detect_seq_type(multi_reader(input, tag_source, strict)?, seq_type)?;
+    let fa_reader = Reader::new(reader);
 
-    let mut out = file_writer(output, compression_level)?;
-    if all {
-        if !noehader {
-            out.write_all(b"seq_name\tlength\tcount_A\tcount_T\tcount_G\tcount_C\tcount_N\n")?;
+    let mut columns = vec!["seq_name".to_string(), "length".to_string()];
+    if all && seq_type.is_protein() {
+        columns.push("mol_weight".to_string());
+        columns.push("pI".to_string());
+        for aa in AMINO_ACIDS.iter().chain(b"X") {
+            columns.push(format!("count_{}", *aa as char));
+        }
+    } else if all {
+        let t = if seq_type == SeqType::Rna { "U" } else { "T" };
+        for nt in ["A", t, "G", "C", "N"] {
+            columns.push(format!("count_{}", nt));
         }
-    } else if !noehader {
-        out.write_all(b"seq_name\tlength\n")?;
     }
-    let mut n = 0usize;
 
-    while rset.fill(&mut fa_reader)? {
-        for rec in rset.iter().map_while(Result::ok) {
-            n += 1;
-            let seq = rec.seq();
-            if all {
-                let mut count_a = 0usize;
-                let mut count_t = 0usize;
-                let mut count_g = 0usize;
-                let mut count_c = 0usize;
-                let mut count_n = 0usize;
-                for (pos, nt) in seq.iter().enumerate() {
-                    match nt {
-                        b'A' | b'a' => count_a += 1,
-                        b'T' | b't' => count_t += 1,
-                        b'G' | b'g' => count_g += 1,
-                        b'C' | b'c' => count_c += 1,
-                        b'N' | b'n' => count_n += 1,
-                        _ => warn!(
-                            "Error DNA base code in sequence {} position: {}",
-                            rec.id_str(),
-                            pos + 1
-                        ),
+    let mut out = ReportWriter::new(
+        file_writer(output, compression_level, threads, output_compression)?,
+        format,
+    );
+    if header {
+        let columns = columns.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        format.write_header(&mut out, &columns)?;
+    }
+    let n = process_records(fa_reader, threads, &mut out, |rec, out_buf| {
+        let seq = rec.seq();
+        let name = match keep {
+            true => rec.id_str(),
+            false => rec.id_str().split_whitespace().next().unwrap_or(""),
+        };
+        let mut values = vec![Cell::from(name), Cell::from(seq.len())];
+        if all && seq_type.is_protein() {
+            values.push(Cell::Float(molecular_weight(&seq), 2));
+            values.push(Cell::Float(isoelectric_point(&seq), 2));
+            values.extend(residue_counts(&seq).map(Cell::from));
+        } else if all {
+            let mut count_a = 0usize;
+            let mut count_t = 0usize;
+            let mut count_g = 0usize;
+            let mut count_c = 0usize;
+            let mut count_n = 0usize;
+            // other bases, eg. IUPAC codes, are warned once per record
+            let (mut other, mut first_other) = (0usize, 0usize);
+            for (pos, nt) in seq.iter().enumerate() {
+                match nt {
+                    b'A' | b'a' => count_a += 1,
+                    b'T' | b't' if seq_type != SeqType::Rna => count_t += 1,
+                    b'U' | b'u' if seq_type == SeqType::Rna => count_t += 1,
+                    b'G' | b'g' => count_g += 1,
+                    b'C' | b'c' => count_c += 1,
+                    b'N' | b'n' => count_n += 1,
+                    _ => {
+                        if other == 0 {
+                            first_other = pos + 1;
+                        }
+                        other += 1;
                     }
                 }
-                let buf = format!(
-                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
-                    match keep {
-                        true => rec.id_str(),
-                        false => rec.id_str().split_whitespace().next().unwrap_or(""),
-                    },
-                    seq.len(),
-                    count_a,
-                    count_t,
-                    count_g,
-                    count_c,
-                    count_n
-                );
-                out.write_all(buf.as_bytes())?;
-            } else {
-                let buf = format!(
-                    "{}\t{}\n",
-                    if keep {
-                        rec.id_str()
-                    } else {
-                        rec.id_str().split_whitespace().next().unwrap_or("")
-                    },
-                    seq.len()
+            }
+            if other > 0 {
+                warn!(
+                    "{} bases not counted in sequence {}, first at position: {}",
+                    other,
+                    rec.id_str(),
+                    first_other
                 );
-                out.write_all(buf.as_bytes())?;
             }
+            values.extend([count_a, count_t, count_g, count_c, count_n].map(Cell::from));
         }
-    }
-    out.flush()?;
+        let row = columns.iter().zip(values).collect::<Vec<_>>();
+        format.write_row(out_buf, &row)?;
+        Ok(true)
+    })?;
+    out.finish()?;
     info!("total sequence number: {}", n);
 
     Ok(())
@@ -1,86 +1,128 @@
 use crate::{
-    cmd::wrap::write_record,
+    cmd::{
+        alphabet::{SeqType, detect_seq_type},
+        wrap::write_record,
+    },
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    report::{Cell, ReportFormat, ReportWriter},
+    utils::{CompressionFormat, file_writer, multi_reader, process_records},
 };
-use log::{error, info};
+use log::info;
 use paraseq::{
-    fasta::{Reader, RecordSet},
+    fasta::{Reader, RefRecord},
     fastx::Record,
 };
-use std::path::Path;
+use std::{io::Write, path::Path};
 
-pub fn sliding_window<P: AsRef<Path> + Copy>(
+#[allow(clippy::too_many_arguments)]
+pub fn sliding_window<P: AsRef<Path>>(
     step: usize,
     wind: usize,
-    file: Option<P>,
-    out: Option<P>,
+    file: &[P],
+    tag_source: bool,
+    strict: bool,
+    out: Option<&P>,
     keep: bool,
+    seq_type: SeqType,
+    header: bool,
+    format: Option<ReportFormat>,
     line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut fp = file_reader(file).map(Reader::new)?;
-    let mut rset = RecordSet::default();
+    let (seq_type, reader) = detect_seq_type(multi_reader(file, tag_source, strict)?, seq_type)?;
+    let fp = Reader::new(reader);
     if step == 0 {
-        error!("step size can't be 0");
-        std::process::exit(1);
+        return Err(FakitError::InvalidArgument(
+            "step size can't be 0".to_string(),
+        ));
+    }
+    if keep && format.is_some() {
+        return Err(FakitError::InvalidArgument(
+            "option -k (--keep) writes fasta records, it can't be used with --format".to_string(),
+        ));
     }
     info!("window size : {}", wind);
     info!("step size: {}", step);
+    if seq_type.is_protein() {
+        info!("gc content is not reported for protein sequences");
+    }
 
-    let mut fo = file_writer(out, compression_level)?;
-    while rset.fill(&mut fp)? {
-        for rec in rset.iter().map_while(Result::ok) {
-            let seq = rec.seq();
-            let len = seq.len();
-            let mut start = 0;
-            let mut windows = wind;
-            loop {
-                if windows < len {
-                    let fa = &seq[start..windows];
-                    let gc = fa
-                        .iter()
-                        .filter(|x| matches!(x, &b'G' | &b'C' | &b'g' | &b'c'))
-                        .count() as f64
-                        / wind as f64;
-                    if keep {
-                        let id_desc =
-                            format!("{} {}-{}:{:.4}", rec.id_str(), start + 1, windows, gc);
-                        write_record(&mut fo, id_desc.as_bytes(), fa, line_width)?;
-                    } else {
-                        fo.write_all(rec.id())?;
-                        let desc = format!(" {}-{}:{:.4}\t", start + 1, windows, gc,);
-                        fo.write_all(desc.as_bytes())?;
-                        fo.write_all(fa)?;
-                        fo.write_all(b"\n")?;
-                    };
-                    start += step;
-                    windows += step;
-                } else {
-                    let fa = &seq[start..len];
-                    let gc = fa
-                        .iter()
-                        .filter(|x| matches!(x, &b'G' | &b'C' | &b'g' | &b'c'))
-                        .count() as f64
-                        / (len - start) as f64;
-                    if keep {
-                        let id_desc = format!("{} {}-{}:{:.4}", rec.id_str(), start + 1, len, gc);
-                        write_record(&mut fo, id_desc.as_bytes(), fa, line_width)?;
-                    } else {
-                        fo.write_all(rec.id())?;
-                        let desc = format!(" {}-{}:{:.4}\t", start + 1, len, gc,);
-                        fo.write_all(desc.as_bytes())?;
-                        fo.write_all(fa)?;
-                        fo.write_all(b"\n")?;
-                    }
-                    // rset for next record
-                    break;
-                }
+    // without --format the plain layout is kept: seqid start-end:gc_rate sequence
+    let mut fo = ReportWriter::new(
+        file_writer(out, compression_level, threads, output_compression)?,
+        format.unwrap_or(ReportFormat::Tsv),
+    );
+    if let Some(format) = format.filter(|_| header) {
+        let columns = match seq_type.is_protein() {
+            true => vec!["id", "start", "end", "sequence"],
+            false => vec!["id", "start", "end", "gc", "sequence"],
+        };
+        format.write_header(&mut fo, &columns)?;
+    }
+    // gc content is not reported for protein
+    let gc_rate = |fa: &[u8]| {
+        if seq_type.is_protein() {
+            return None;
+        }
+        let gc = fa
+            .iter()
+            .filter(|x| matches!(x, &b'G' | &b'C' | &b'g' | &b'c'))
+            .count();
+        Some(gc as f64 / fa.len() as f64)
+    };
+    let write_window = |buf: &mut Vec<u8>, rec: &RefRecord, start: usize, end: usize| {
+        let fa = &rec.seq()[start..end];
+        let gc = gc_rate(fa);
+        let label = match gc {
+            Some(gc) => format!("{}-{}:{:.4}", start + 1, end, gc),
+            None => format!("{}-{}", start + 1, end),
+        };
+        if keep {
+            let id_desc = format!("{} {}", rec.id_str(), label);
+            write_record(buf, id_desc.as_bytes(), fa, line_width)?;
+        } else if let Some(format) = format {
+            let mut row = vec![
+                ("id", Cell::from(rec.id_str())),
+                ("start", Cell::from(start + 1)),
+                ("end", Cell::from(end)),
+            ];
+            if let Some(gc) = gc {
+                row.push(("gc", Cell::Float(gc, 4)));
             }
+            row.push((
+                "sequence",
+                Cell::Str(String::from_utf8_lossy(fa).to_string()),
+            ));
+            format.write_row(buf, &row)?;
+        } else {
+            buf.write_all(rec.id())?;
+            buf.write_all(format!(" {}\t", label).as_bytes())?;
+            buf.write_all(fa)?;
+            buf.write_all(b"\n")?;
         }
-    }
+        Ok::<(), FakitError>(())
+    };
+    process_records(fp, threads, &mut fo, |rec, buf| {
+        let len = rec.seq().len();
+        let mut start = 0;
+        let mut windows = wind;
+        loop {
+            if windows < len {
+                write_window(buf, rec, start, windows)?;
+                start += step;
+                windows += step;
+            } else {
+                write_window(buf, rec, start, len)?;
+                // rset for next record
+                break;
+            }
+        }
+        Ok(true)
+    })?;
 
-    fo.flush()?;
+    fo.finish()?;
 
     Ok(())
 }
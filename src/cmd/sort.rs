@@ -1,9 +1,9 @@
 use crate::{
     cmd::wrap::write_record,
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader},
 };
-use log::{error, info};
+use log::info;
 use paraseq::{
     fasta::{Reader, RecordSet},
     fastx::Record,
@@ -11,18 +11,22 @@ use paraseq::{
 use std::path::Path;
 
 #[allow(clippy::too_many_arguments)]
-pub fn sort_fasta<P: AsRef<Path> + Copy>(
-    file: Option<P>,
+pub fn sort_fasta<P: AsRef<Path>>(
+    file: &[P],
+    tag_source: bool,
+    strict: bool,
     sort_by_name: bool,
     sort_by_seq: bool,
     sort_by_gc: bool,
     sort_by_length: bool,
     reverse: bool,
-    out: Option<P>,
+    out: Option<&P>,
     line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut fa_reader = file_reader(file).map(Reader::new)?;
+    let mut fa_reader = multi_reader(file, tag_source, strict).map(Reader::new)?;
 
     let mut n = 0;
     if sort_by_gc {
@@ -38,21 +42,23 @@ pub fn sort_fasta<P: AsRef<Path> + Copy>(
         n += 1;
     }
     if n > 1 {
-        error!(
-            "only one of the flags -l (--sort-by-length), -n (--sort-by-name), -g (--sort-by-gc) and -s (--sort-by-seq) is allowed"
-        );
-        std::process::exit(1);
+        return Err(FakitError::InvalidArgument(
+            "only one of the flags -l (--sort-by-length), -n (--sort-by-name), -g (--sort-by-gc) and -s (--sort-by-seq) is allowed".to_string(),
+        ));
     }
     if n == 0 {
-        error!("please specifiy one of the flags: -l, -n, -g, -s");
-        std::process::exit(1);
+        return Err(FakitError::InvalidArgument(
+            "please specifiy one of the flags: -l, -n, -g, -s".to_string(),
+        ));
     }
 
     let mut vec_reads = vec![];
 
     let mut rset = RecordSet::default();
+    let mut nrec = 0usize;
     while rset.fill(&mut fa_reader)? {
-        for rec in rset.iter().map_while(Result::ok) {
+        for rec in batch_records(&rset, &mut nrec) {
+            let rec = rec?;
             vec_reads.push((rec.id_str().to_string(), rec.seq_str().to_string()));
         }
     }
@@ -120,7 +126,7 @@ pub fn sort_fasta<P: AsRef<Path> + Copy>(
     }
 
     info!("sort done, start to output ...");
-    let mut fa_writer = file_writer(out, compression_level)?;
+    let mut fa_writer = file_writer(out, compression_level, threads, output_compression)?;
     for rec in vec_reads {
         write_record(
             &mut fa_writer,
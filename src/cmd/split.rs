@@ -1,7 +1,7 @@
 use crate::{
     cmd::wrap::write_record,
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader},
 };
 use paraseq::{
     fasta::{Reader, RecordSet},
@@ -10,26 +10,34 @@ use paraseq::{
 use std::path::Path;
 use std::path::PathBuf;
 
-pub fn split_fa<P: AsRef<Path> + Copy>(
-    input: Option<P>,
+#[allow(clippy::too_many_arguments)]
+pub fn split_fa<P: AsRef<Path>>(
+    input: &[P],
+    tag_source: bool,
+    strict: bool,
     ext: String,
     keep: bool,
-    outdir: Option<P>,
+    outdir: Option<&P>,
     line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut fp = file_reader(input).map(Reader::new)?;
+    let mut fp = multi_reader(input, tag_source, strict).map(Reader::new)?;
     let mut rset = RecordSet::default();
 
+    let mut nrec = 0usize;
     while rset.fill(&mut fp)? {
-        for rec in rset.iter().map_while(Result::ok) {
+        for rec in batch_records(&rset, &mut nrec) {
+            let rec = rec?;
             let id = rec.id_str().split_whitespace().next().unwrap();
             let path = match outdir {
                 Some(dir) => dir.as_ref().join(format!("{}.{}", id, ext)),
                 None => PathBuf::from(format!("./{}.{}", id, ext)),
             };
 
-            let mut writer = file_writer(Some(&path), compression_level)?;
+            let mut writer =
+                file_writer(Some(&path), compression_level, threads, output_compression)?;
             match keep {
                 true => write_record(&mut writer, rec.id(), &rec.seq(), line_width)?,
                 false => write_record(&mut writer, id.as_bytes(), &rec.seq(), line_width)?,
@@ -1,15 +1,17 @@
 use crate::{
     cmd::wrap::write_record,
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader},
 };
-use log::{error, info};
+use log::info;
 use paraseq::fasta::{Reader, RecordSet};
 use std::path::Path;
 
 #[allow(clippy::too_many_arguments)]
-pub fn split_chunk<P: AsRef<Path> + Copy>(
-    file: Option<P>,
+pub fn split_chunk<P: AsRef<Path>>(
+    file: &[P],
+    tag_source: bool,
+    strict: bool,
     num: usize,
     gzip: bool,
     bzip2: bool,
@@ -17,8 +19,10 @@ pub fn split_chunk<P: AsRef<Path> + Copy>(
     out_pre: &str,
     line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut fa_reader = file_reader(file).map(Reader::new)?;
+    let mut fa_reader = multi_reader(file, tag_source, strict).map(Reader::new)?;
     let mut rset = RecordSet::default();
     let mut n = 0;
     if gzip {
@@ -31,8 +35,9 @@ pub fn split_chunk<P: AsRef<Path> + Copy>(
         n += 1;
     }
     if n > 1 {
-        error!("only one of the flags --gzip , --xz and --bzip2 is allowed");
-        std::process::exit(1);
+        return Err(FakitError::InvalidArgument(
+            "only one of the flags --gzip , --xz and --bzip2 is allowed".to_string(),
+        ));
     }
 
     let (mut flag, mut index) = (0usize, 0usize);
@@ -46,11 +51,18 @@ pub fn split_chunk<P: AsRef<Path> + Copy>(
         format!("{}{}.fasta", out_pre, index)
     };
 
-    let mut fh = vec![file_writer(Some(&out), compression_level)?];
+    let mut fh = vec![file_writer(
+        Some(&out),
+        compression_level,
+        threads,
+        output_compression,
+    )?];
 
     info!("start to write file: {}", out);
+    let mut nrec = 0usize;
     while rset.fill(&mut fa_reader)? {
-        for rec in rset.iter().map_while(Result::ok) {
+        for rec in batch_records(&rset, &mut nrec) {
+            let rec = rec?;
             if flag < num {
                 let mut fhthis = fh.get_mut(index).unwrap();
                 write_record(&mut fhthis, rec.id(), &rec.seq(), line_width)?;
@@ -66,7 +78,12 @@ pub fn split_chunk<P: AsRef<Path> + Copy>(
                 } else {
                     format!("{}{}.fasta", out_pre, index)
                 };
-                fh.push(file_writer(Some(&out), compression_level)?);
+                fh.push(file_writer(
+                    Some(&out),
+                    compression_level,
+                    threads,
+                    output_compression,
+                )?);
                 let mut fhthis = fh.get_mut(index).unwrap();
 
                 info!("start to write file: {}", out);
@@ -1,13 +1,30 @@
 use crate::{
+    cmd::alphabet::{AMINO_ACIDS, SeqType, detect_seq_type, residue_counts},
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    report::{Cell, ReportFormat, ReportWriter},
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader},
 };
-use log::{error, warn};
+use log::{info, warn};
 use paraseq::{
     fasta::{Reader, RecordSet},
     fastx::Record,
 };
-use std::path::Path;
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeMap,
+    io::BufRead,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+// upper bounds of sequence length histogram buckets, the last bucket has no upper bound
+const HIST_BOUNDS: [usize; 9] = [
+    100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000,
+];
 
 #[derive(Debug)]
 struct Seqinfo {
@@ -21,9 +38,16 @@ struct Seqinfo {
     count_g: usize,
     count_c: usize,
     count_n: usize,
+    // protein residue counts in AMINO_ACIDS order, and other residues
+    residues: [usize; 21],
     rate_gc: f64,
     rate_n: f64,
     mean_len: f64,
+    // all sequence lengths, sorted from longest to shortest when the file is done
+    lengths: Vec<usize>,
+    // runs of N in nucleotide sequences
+    gap_count: usize,
+    max_gap: usize,
 }
 
 impl Seqinfo {
@@ -39,113 +63,383 @@ impl Seqinfo {
             count_g: 0,
             count_c: 0,
             count_n: 0,
+            residues: [0; 21],
             rate_gc: 0.,
             rate_n: 0.,
             mean_len: 0.,
+            lengths: vec![],
+            gap_count: 0,
+            max_gap: 0,
         }
     }
-    fn mean(&mut self) {
-        self.mean_len = self.sum_len as f64 / self.num_seq as f64;
+    fn add(&mut self, other: &Seqinfo) {
+        self.sum_len += other.sum_len;
+        self.num_seq += other.num_seq;
+        self.count_a += other.count_a;
+        self.count_t += other.count_t;
+        self.count_g += other.count_g;
+        self.count_c += other.count_c;
+        self.count_n += other.count_n;
+        for (total, count) in self.residues.iter_mut().zip(other.residues) {
+            *total += count;
+        }
+        self.lengths.extend_from_slice(&other.lengths);
+        self.gap_count += other.gap_count;
+        self.max_gap = self.max_gap.max(other.max_gap);
     }
-    fn rate(&mut self) {
+    // length and composition stats once all sequences are counted, an empty file gets zeros
+    fn finish(&mut self) {
+        self.lengths.sort_unstable_by(|a, b| b.cmp(a));
+        self.max_len = self.lengths.first().copied().unwrap_or(0);
+        self.min_len = self.lengths.last().copied().unwrap_or(0);
+        if self.num_seq > 0 {
+            self.mean_len = self.sum_len as f64 / self.num_seq as f64;
+        }
         let total = self.count_a + self.count_t + self.count_g + self.count_c + self.count_n;
-        self.rate_gc = (self.count_g + self.count_c) as f64 / total as f64;
-        self.rate_n = self.count_n as f64 / total as f64;
+        if total > 0 {
+            self.rate_gc = (self.count_g + self.count_c) as f64 / total as f64;
+            self.rate_n = self.count_n as f64 / total as f64;
+        }
+    }
+    fn gaps(&mut self, seq: &[u8]) {
+        let mut run = 0usize;
+        for nt in seq.iter().chain([&b'\0']) {
+            if matches!(nt, b'N' | b'n') {
+                run += 1;
+            } else if run > 0 {
+                self.gap_count += 1;
+                self.max_gap = self.max_gap.max(run);
+                run = 0;
+            }
+        }
+    }
+    // Nx and Lx: the length of the sequence that brings the cumulative length to x% of total,
+    // and the number of sequences needed
+    fn nx(&self, x: usize, total: usize) -> Option<(usize, usize)> {
+        let target = total as f64 * x as f64 / 100.0;
+        let mut sum = 0usize;
+        for (i, len) in self.lengths.iter().enumerate() {
+            sum += len;
+            if sum as f64 >= target {
+                return Some((*len, i + 1));
+            }
+        }
+        None
+    }
+    // area under the Nx curve
+    fn aun(&self) -> f64 {
+        if self.sum_len == 0 {
+            return 0.;
+        }
+        let squares = self
+            .lengths
+            .iter()
+            .map(|x| (*x as f64) * (*x as f64))
+            .sum::<f64>();
+        squares / self.sum_len as f64
+    }
+    fn histogram(&self) -> [usize; HIST_BOUNDS.len() + 1] {
+        let mut hist = [0usize; HIST_BOUNDS.len() + 1];
+        for len in self.lengths.iter() {
+            let i = HIST_BOUNDS
+                .iter()
+                .position(|x| len < x)
+                .unwrap_or(HIST_BOUNDS.len());
+            hist[i] += 1;
+        }
+        hist
     }
 }
 
-pub fn summary_fa<P: AsRef<Path> + Copy>(
-    input: Vec<P>,
+// summary columns of a file
+fn summary_row(
+    info: &Seqinfo,
     all: bool,
-    output: Option<P>,
-    compression_level: u32,
-) -> Result<(), FakitError> {
-    if input.is_empty() {
-        error!("{}", FakitError::FileNotFound);
-        std::process::exit(1);
+    seq_type: SeqType,
+    genome_size: Option<usize>,
+    hist: bool,
+) -> Vec<(String, Cell)> {
+    let mut row = vec![("file".to_string(), Cell::from(info.name.as_str()))];
+    if all && seq_type.is_protein() {
+        for (aa, count) in AMINO_ACIDS.iter().chain(b"X").zip(info.residues) {
+            row.push((format!("count_{}", *aa as char), Cell::from(count)));
+        }
+    } else if all {
+        let t = if seq_type == SeqType::Rna { "U" } else { "T" };
+        row.push(("count_A".to_string(), Cell::from(info.count_a)));
+        row.push(("count_C".to_string(), Cell::from(info.count_c)));
+        row.push(("count_G".to_string(), Cell::from(info.count_g)));
+        row.push((format!("count_{}", t), Cell::from(info.count_t)));
+        row.push(("count_N".to_string(), Cell::from(info.count_n)));
+        row.push(("rate_GC".to_string(), Cell::Float(info.rate_gc, 2)));
+        row.push(("rate_N".to_string(), Cell::Float(info.rate_n, 2)));
     }
+    row.push(("num_seq".to_string(), Cell::from(info.num_seq)));
+    row.push(("sum_len".to_string(), Cell::from(info.sum_len)));
+    row.push(("min_len".to_string(), Cell::from(info.min_len)));
+    row.push(("mean_len".to_string(), Cell::Float(info.mean_len, 0)));
+    row.push(("max_len".to_string(), Cell::from(info.max_len)));
 
-    if input.is_empty() {
-        error!("{}", FakitError::FileNotFound);
-        std::process::exit(1);
+    let mut nx = |prefix: &str, total: usize| {
+        for x in [50, 90] {
+            let (n, l) = match info.nx(x, total) {
+                Some((n, l)) => (Cell::from(n), Cell::from(l)),
+                None => (Cell::Null, Cell::Null),
+            };
+            row.push((format!("N{}{}", prefix, x), n));
+            row.push((format!("L{}{}", prefix, x), l));
+        }
+    };
+    nx("", info.sum_len);
+    if let Some(size) = genome_size {
+        nx("G", size);
     }
-    let mut fo = file_writer(output, compression_level)?;
-    if all {
-        fo.write_all("file\tcount_A\tcount_C\tcount_G\tcount_T\tcount_N\trate_GC\trate_N\tnum_seq\tsum_len\tmin_len\tmean_len\tmax_len\n".as_bytes())?;
-    } else {
-        fo.write_all("file\tnum_seq\tsum_len\tmin_len\tmean_len\tmax_len\n".as_bytes())?;
+    row.push(("auN".to_string(), Cell::Float(info.aun(), 2)));
+    if !seq_type.is_protein() {
+        row.push(("gap_count".to_string(), Cell::from(info.gap_count)));
+        row.push(("gap_sum".to_string(), Cell::from(info.count_n)));
+        row.push(("max_gap".to_string(), Cell::from(info.max_gap)));
     }
 
-    for file in input {
-        let mut info = Seqinfo::new(file.as_ref().to_string_lossy().to_string());
-        let mut min: Option<usize> = None;
+    if hist {
+        let mut low = 0;
+        for (i, count) in info.histogram().into_iter().enumerate() {
+            let name = match HIST_BOUNDS.get(i) {
+                Some(high) => format!("len_{}-{}", low, high - 1),
+                None => format!("len_{}+", low),
+            };
+            row.push((name, Cell::from(count)));
+            low = HIST_BOUNDS.get(i).copied().unwrap_or(low);
+        }
+    }
+    row
+}
 
-        let mut fp = file_reader(Some(file)).map(Reader::new)?;
-        let mut rset = RecordSet::default();
+// count sequences of a fasta file
+fn summary_file<P: AsRef<Path>>(
+    file: P,
+    reader: Box<dyn BufRead + Send>,
+    seq_type: SeqType,
+) -> Result<Seqinfo, FakitError> {
+    let mut info = Seqinfo::new(file.as_ref().to_string_lossy().to_string());
+    let mut fp = Reader::new(reader);
+    let mut rset = RecordSet::default();
+    // other bases, eg. IUPAC codes, are warned once per file
+    let (mut other, mut first_other) = (0usize, String::new());
 
-        while rset.fill(&mut fp)? {
-            for rec in rset.iter().map_while(Result::ok) {
-                info.num_seq += 1;
-                let seq_len = rec.seq().len();
-                info.sum_len += seq_len;
+    let mut nrec = 0usize;
+    while rset.fill(&mut fp)? {
+        for rec in batch_records(&rset, &mut nrec) {
+            let rec = rec?;
+            info.num_seq += 1;
+            let seq_len = rec.seq().len();
+            info.sum_len += seq_len;
+            info.lengths.push(seq_len);
 
-                if seq_len > info.max_len {
-                    info.max_len = seq_len;
+            if seq_type.is_protein() {
+                for (total, count) in info.residues.iter_mut().zip(residue_counts(&rec.seq())) {
+                    *total += count;
                 }
-                min = if let Some(min) = min {
-                    if seq_len < min {
-                        Some(seq_len)
-                    } else {
-                        Some(min)
+                continue;
+            }
+            info.gaps(&rec.seq());
+            for (pos, nt) in rec.seq().iter().enumerate() {
+                match nt {
+                    &b'A' | &b'a' => info.count_a += 1,
+                    &b'T' | &b't' if seq_type != SeqType::Rna => info.count_t += 1,
+                    &b'U' | &b'u' if seq_type == SeqType::Rna => info.count_t += 1,
+                    &b'G' | &b'g' => info.count_g += 1,
+                    &b'C' | &b'c' => info.count_c += 1,
+                    &b'N' | &b'n' => info.count_n += 1,
+                    _ => {
+                        if other == 0 {
+                            first_other = format!("{} position: {}", rec.id_str(), pos + 1);
+                        }
+                        other += 1;
                     }
-                } else {
-                    Some(seq_len)
-                };
-                for (pos, nt) in rec.seq().iter().enumerate() {
-                    match nt {
-                        &b'A' | &b'a' => info.count_a += 1,
-                        &b'T' | &b't' => info.count_t += 1,
-                        &b'G' | &b'g' => info.count_g += 1,
-                        &b'C' | &b'c' => info.count_c += 1,
-                        &b'N' | &b'n' => info.count_n += 1,
-                        _ => warn!(
-                            "Error DNA base code in sequence {} position: {}",
-                            rec.id_str(),
-                            pos + 1
-                        ),
+                }
+            }
+        }
+    }
+    if info.num_seq == 0 {
+        warn!("no sequence found in file: {}", info.name);
+    }
+    if other > 0 {
+        warn!(
+            "{} bases not counted in file: {}, first in sequence {}",
+            other, info.name, first_other
+        );
+    }
+    info.finish();
+    Ok(info)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn summary_fa<P: AsRef<Path> + Copy + Sync>(
+    input: Vec<P>,
+    all: bool,
+    mut seq_type: SeqType,
+    genome_size: Option<usize>,
+    hist: bool,
+    tabular: bool,
+    strict: bool,
+    header: bool,
+    format: ReportFormat,
+    output: Option<P>,
+    compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
+) -> Result<(), FakitError> {
+    if input.is_empty() {
+        return Err(FakitError::InvalidArgument(
+            "no input file, eg. fakit summ *.fa".to_string(),
+        ));
+    }
+    // sequence type of the first file is used for all files, so the columns are the same,
+    // the reader of first file is kept for its summary, as stdin can't be opened twice
+    let mut first_reader = None;
+    if seq_type == SeqType::Auto {
+        let (detected, reader) =
+            detect_seq_type(multi_reader(&input[..1], false, strict)?, seq_type)?;
+        seq_type = detected;
+        first_reader = Some(reader);
+    }
+    let first_reader = Mutex::new(first_reader);
+    let mut fo = ReportWriter::new(
+        file_writer(output, compression_level, threads, output_compression)?,
+        format,
+    );
+    let mut total = Seqinfo::new("total".to_string());
+    // header is written with the first row, as columns depend on the sequence type
+    let mut header = header;
+    let mut write_info = |info: &Seqinfo| -> Result<(), FakitError> {
+        let row = summary_row(info, all, seq_type, genome_size, hist);
+        if header {
+            header = false;
+            let columns = row.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>();
+            format.write_header(&mut fo, &columns)?;
+        }
+        format.write_row(&mut fo, &row)?;
+        Ok(())
+    };
+
+    let workers = threads.clamp(1, input.len());
+    info!("summary {} files with {} threads", input.len(), workers);
+    let next_file = AtomicUsize::new(0);
+    thread::scope(|scope| -> Result<(), FakitError> {
+        let (out_tx, out_rx) = mpsc::channel::<(usize, Result<Seqinfo, FakitError>)>();
+        for _ in 0..workers {
+            let out_tx = out_tx.clone();
+            let (next_file, input, first_reader) = (&next_file, &input, &first_reader);
+            scope.spawn(move || {
+                loop {
+                    let idx = next_file.fetch_add(1, Ordering::Relaxed);
+                    let Some(file) = input.get(idx) else {
+                        break;
+                    };
+                    let first = if idx == 0 {
+                        first_reader.lock().take()
+                    } else {
+                        None
+                    };
+                    let reader = match first {
+                        Some(reader) => Ok(reader),
+                        None => multi_reader(&[*file], false, strict),
+                    };
+                    let res = reader.and_then(|reader| summary_file(*file, reader, seq_type));
+                    if out_tx.send((idx, res)).is_err() {
+                        break;
                     }
                 }
+            });
+        }
+        drop(out_tx);
+
+        // write file summaries back in input order
+        let mut pending = BTreeMap::new();
+        let mut next = 0usize;
+        for (idx, res) in out_rx {
+            pending.insert(idx, res);
+            while let Some(res) = pending.remove(&next) {
+                let info = res?;
+                write_info(&info)?;
+                if tabular {
+                    total.add(&info);
+                }
+                next += 1;
             }
         }
-        info.min_len = min.unwrap();
-        info.mean();
-        info.rate();
-
-        let res = match all {
-            true => format!(
-                "{}\t{}\t{}\t{}\t{}\t{}\t{:.2}\t{:.2}\t{}\t{}\t{}\t{:.0}\t{}\n",
-                info.name,
-                info.count_a,
-                info.count_c,
-                info.count_g,
-                info.count_t,
-                info.count_n,
-                info.rate_gc,
-                info.rate_n,
-                info.num_seq,
-                info.sum_len,
-                info.min_len,
-                info.mean_len,
-                info.max_len
-            ),
-            false => format!(
-                "{}\t{}\t{}\t{}\t{:.0}\t{}\n",
-                info.name, info.num_seq, info.sum_len, info.min_len, info.mean_len, info.max_len
-            ),
-        };
-        fo.write_all(res.as_bytes())?;
-    }
-    fo.flush()?;
+        Ok(())
+    })?;
+
+    if tabular {
+        total.finish();
+        write_info(&total)?;
+    }
+    fo.finish()?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info_of(lengths: &[usize]) -> Seqinfo {
+        let mut info = Seqinfo::new("test".to_string());
+        info.lengths = lengths.to_vec();
+        info.num_seq = lengths.len();
+        info.sum_len = lengths.iter().sum();
+        info.finish();
+        info
+    }
+
+    #[test]
+    fn nx_and_lx() {
+        let info = info_of(&[10, 50, 20, 80, 30, 70, 40]);
+        assert_eq!((info.min_len, info.max_len), (10, 80));
+        assert_eq!(info.nx(50, info.sum_len), Some((70, 2)));
+        assert_eq!(info.nx(90, info.sum_len), Some((30, 5)));
+        assert_eq!(info.nx(100, info.sum_len), Some((10, 7)));
+        // NG50 with a genome larger than the assembly
+        assert_eq!(info.nx(50, 500), Some((30, 5)));
+        assert_eq!(info.nx(50, 1000), None);
+    }
+
+    #[test]
+    fn area_under_nx() {
+        let info = info_of(&[10, 50, 20, 80, 30, 70, 40]);
+        assert!((info.aun() - 56.0).abs() < 1e-9);
+        let info = info_of(&[100, 100]);
+        assert!((info.aun() - 100.0).abs() < 1e-9);
+        assert_eq!(info_of(&[]).aun(), 0.);
+        // square of the length doesn't fit in usize
+        let info = info_of(&[1 << 40, 1 << 40]);
+        assert_eq!(info.aun(), (1u64 << 40) as f64);
+    }
+
+    #[test]
+    fn empty_file() {
+        let info = info_of(&[]);
+        assert_eq!((info.min_len, info.max_len, info.mean_len), (0, 0, 0.));
+        assert_eq!(info.nx(50, info.sum_len), None);
+    }
+
+    #[test]
+    fn gap_runs() {
+        let mut info = Seqinfo::new("test".to_string());
+        info.gaps(b"NNACnnnnGN");
+        info.gaps(b"ACGT");
+        assert_eq!((info.gap_count, info.max_gap), (3, 4));
+    }
+
+    #[test]
+    fn length_histogram() {
+        let info = info_of(&[99, 100, 499, 1_000_000, 5]);
+        let hist = info.histogram();
+        assert_eq!(hist[0], 2);
+        assert_eq!(hist[1], 2);
+        assert_eq!(hist[HIST_BOUNDS.len()], 1);
+        assert_eq!(hist.iter().sum::<usize>(), 5);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{
     cmd::wrap::write_record,
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader, reads_stdin},
 };
 use log::info;
 use paraseq::{
@@ -12,27 +12,37 @@ use rand::{Rng, prelude::*};
 use rand_pcg::Pcg64;
 use std::path::Path;
 
-pub fn select_fasta<P: AsRef<Path> + Copy>(
-    file: Option<P>,
+#[allow(clippy::too_many_arguments)]
+pub fn select_fasta<P: AsRef<Path>>(
+    file: &[P],
+    tag_source: bool,
+    strict: bool,
     n: usize,
     seed: u64,
     two_pass: bool,
-    out: Option<P>,
+    out: Option<&P>,
     line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut fa_reader = file_reader(file).map(Reader::new)?;
+    let mut fa_reader = multi_reader(file, tag_source, strict).map(Reader::new)?;
     let mut rset = RecordSet::default();
     info!("rand seed: {}", seed);
-    let mut writer = file_writer(out, compression_level)?;
+    let mut writer = file_writer(out, compression_level, threads, output_compression)?;
 
     let mut rng = Pcg64::seed_from_u64(seed);
     let mut order = 0usize;
     if two_pass {
         info!("enable two pass mode");
+        if reads_stdin(file) {
+            return Err(FakitError::TwoPassNotAllowedStdin);
+        }
         let mut get: Vec<usize> = Vec::with_capacity(n);
+        let mut nrec = 0usize;
         while rset.fill(&mut fa_reader)? {
-            for _ in rset.iter().map_while(Result::ok) {
+            for rec in batch_records(&rset, &mut nrec) {
+                rec?;
                 if order < n {
                     get.push(order);
                 } else {
@@ -48,11 +58,13 @@ pub fn select_fasta<P: AsRef<Path> + Copy>(
         order = 0;
         get.sort_unstable(); // keep the order
         info!("all records has been readed into memory, start write to output ...");
-        let mut fa_reader2 = file_reader(file).map(Reader::new)?;
+        let mut fa_reader2 = multi_reader(file, tag_source, strict).map(Reader::new)?;
         let mut rset2 = RecordSet::default();
         let mut idx = 0usize;
+        let mut nrec = 0usize;
         while rset2.fill(&mut fa_reader2)? {
-            for rec in rset2.iter().map_while(Result::ok) {
+            for rec in batch_records(&rset2, &mut nrec) {
+                let rec = rec?;
                 if idx < get.len() && order == get[idx] {
                     write_record(&mut writer, rec.id(), &rec.seq(), line_width)?;
                     idx += 1;
@@ -65,8 +77,10 @@ pub fn select_fasta<P: AsRef<Path> + Copy>(
         }
     } else {
         let mut get = Vec::with_capacity(n);
+        let mut nrec = 0usize;
         while rset.fill(&mut fa_reader)? {
-            for rec in rset.iter().map_while(Result::ok) {
+            for rec in batch_records(&rset, &mut nrec) {
+                let rec = rec?;
                 if order < n {
                     get.push((order, rec.id_str().to_owned(), rec.seq_str().into_owned()));
                 } else {
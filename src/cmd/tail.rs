@@ -1,46 +1,53 @@
 use crate::{
     cmd::wrap::write_record,
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader, reads_stdin},
 };
-use log::{error, info};
+use log::info;
 use paraseq::{
     fasta::{Reader, RecordSet},
     fastx::Record,
 };
 use std::{io::BufReader, path::Path};
 
-pub fn tail_n_records<P: AsRef<Path> + Copy>(
+#[allow(clippy::too_many_arguments)]
+pub fn tail_n_records<P: AsRef<Path>>(
     number: usize,
-    input: Option<P>,
+    input: &[P],
+    tag_source: bool,
+    strict: bool,
     two_pass: bool,
-    output: Option<P>,
+    output: Option<&P>,
     line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut fdr = file_reader(input).map(BufReader::new).map(Reader::new)?;
+    let mut fdr = multi_reader(input, tag_source, strict)
+        .map(BufReader::new)
+        .map(Reader::new)?;
     let mut rset = RecordSet::default();
 
-    let mut wdr = file_writer(output, compression_level)?;
+    let mut wdr = file_writer(output, compression_level, threads, output_compression)?;
     if two_pass {
         info!("2-pass mode enabled, reading file twice to get tail records");
-        if input.is_none() {
-            error!(
-                "{}: 2-pass mode is not allowed when reading from stdin. Please provide a file input.",
-                FakitError::TwoPassNotAllowedStdin
-            );
-            std::process::exit(1);
+        if reads_stdin(input) {
+            return Err(FakitError::TwoPassNotAllowedStdin);
         }
 
         let mut total = 0usize;
+        let mut nrec = 0usize;
         while rset.fill(&mut fdr)? {
-            for _ in rset.iter().map_while(Result::ok) {
+            for rec in batch_records(&rset, &mut nrec) {
+                rec?;
                 total += 1;
             }
         }
         info!("total fasta sequences number: {}", total);
 
-        let mut fdr2 = file_reader(input).map(BufReader::new).map(Reader::new)?;
+        let mut fdr2 = multi_reader(input, tag_source, strict)
+            .map(BufReader::new)
+            .map(Reader::new)?;
         let mut rset2 = RecordSet::default();
 
         let mut count = 0usize;
@@ -50,8 +57,10 @@ pub fn tail_n_records<P: AsRef<Path> + Copy>(
             total - number
         };
 
+        let mut nrec = 0usize;
         while rset2.fill(&mut fdr2)? {
-            for rec in rset2.iter().map_while(Result::ok) {
+            for rec in batch_records(&rset2, &mut nrec) {
+                let rec = rec?;
                 if count >= skip_n {
                     write_record(&mut wdr, rec.id(), &rec.seq(), line_width)?;
                 }
@@ -62,8 +71,10 @@ pub fn tail_n_records<P: AsRef<Path> + Copy>(
         let mut records = Vec::with_capacity(number);
         let mut count = 0usize;
 
+        let mut nrec = 0usize;
         while rset.fill(&mut fdr)? {
-            for rec in rset.iter().map_while(Result::ok) {
+            for rec in batch_records(&rset, &mut nrec) {
+                let rec = rec?;
                 let info = (rec.id_str().to_string(), rec.seq_str().to_string());
                 if count < number {
                     records.push(info);
@@ -1,26 +1,35 @@
 use crate::{
     cmd::wrap::write_record,
     errors::FakitError,
-    utils::{file_reader, file_writer},
+    utils::{CompressionFormat, batch_records, file_writer, multi_reader},
 };
 use log::info;
 use paraseq::fasta::{Reader, RecordSet};
 use std::{io::BufReader, path::Path};
 
-pub fn top_n_records<P: AsRef<Path> + Copy>(
+#[allow(clippy::too_many_arguments)]
+pub fn top_n_records<P: AsRef<Path>>(
     number: usize,
-    input: Option<P>,
-    output: Option<P>,
+    input: &[P],
+    tag_source: bool,
+    strict: bool,
+    output: Option<&P>,
     line_width: usize,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<(), FakitError> {
-    let mut fdr = file_reader(input).map(BufReader::new).map(Reader::new)?;
+    let mut fdr = multi_reader(input, tag_source, strict)
+        .map(BufReader::new)
+        .map(Reader::new)?;
     let mut rset = RecordSet::default();
-    let mut wdr = file_writer(output, compression_level)?;
+    let mut wdr = file_writer(output, compression_level, threads, output_compression)?;
 
     let mut count = 0usize;
+    let mut nrec = 0usize;
     'outer: while rset.fill(&mut fdr)? {
-        for rec in rset.iter().map_while(Result::ok) {
+        for rec in batch_records(&rset, &mut nrec) {
+            let rec = rec?;
             if count >= number {
                 break 'outer;
             }
@@ -0,0 +1,101 @@
+use crate::{
+    cmd::{codon::CodonTable, reverse::reverse_complement, wrap::write_record},
+    errors::FakitError,
+    utils::{CompressionFormat, file_writer, multi_reader, process_records},
+};
+use log::info;
+use paraseq::{fasta::Reader, fastx::Record};
+use std::path::Path;
+
+/// Translate a nucleotide sequence in frame 1, the trailing incomplete codon is dropped.
+pub fn translate_seq(seq: &[u8], table: &CodonTable, trim_stop: bool, init_start: bool) -> Vec<u8> {
+    let mut protein = Vec::with_capacity(seq.len() / 3);
+    for (i, codon) in seq.chunks_exact(3).enumerate() {
+        let aa = if i == 0 && init_start && table.is_start(codon) {
+            b'M'
+        } else {
+            table.translate(codon)
+        };
+        if aa == b'*' && trim_stop {
+            break;
+        }
+        protein.push(aa);
+    }
+    protein
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn translate_fasta<P: AsRef<Path>>(
+    input: &[P],
+    tag_source: bool,
+    strict: bool,
+    table: u8,
+    frames: &[i8],
+    trim_stop: bool,
+    init_start: bool,
+    output: Option<&P>,
+    line_width: usize,
+    compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
+) -> Result<(), FakitError> {
+    let table = CodonTable::get(table)?;
+    let mut frame_list: Vec<i8> = vec![];
+    for &frame in frames {
+        let expand = match frame {
+            6 => vec![1, 2, 3, -1, -2, -3],
+            1..=3 | -3..=-1 => vec![frame],
+            _ => {
+                return Err(FakitError::InvalidArgument(format!(
+                    "invalid frame {}, allowed: 1, 2, 3, -1, -2, -3 and 6 for all six frames",
+                    frame
+                )));
+            }
+        };
+        for frame in expand {
+            if !frame_list.contains(&frame) {
+                frame_list.push(frame);
+            }
+        }
+    }
+    info!("translation table {}: {}", table.id, table.name);
+
+    let reader = multi_reader(input, tag_source, strict).map(Reader::new)?;
+    let mut writer = file_writer(output, compression_level, threads, output_compression)?;
+
+    let count = process_records(reader, threads, &mut writer, |rec, buf| {
+        let seq = rec.seq();
+        let rev = if frame_list.iter().any(|x| *x < 0) {
+            reverse_complement(&seq)
+        } else {
+            vec![]
+        };
+        for &frame in frame_list.iter() {
+            let offset = frame.unsigned_abs() as usize - 1;
+            let strand = if frame > 0 { &seq[..] } else { &rev[..] };
+            let protein = translate_seq(
+                strand.get(offset..).unwrap_or_default(),
+                table,
+                trim_stop,
+                init_start,
+            );
+            // frame is added to sequence id when more than one frame is translated
+            if frame_list.len() > 1 {
+                let id = rec.id_str();
+                let (name, desc) = id.split_once(' ').unwrap_or((id, ""));
+                let id = match desc {
+                    "" => format!("{}_frame={}", name, frame),
+                    _ => format!("{}_frame={} {}", name, frame, desc),
+                };
+                write_record(buf, id.as_bytes(), &protein, line_width)?;
+            } else {
+                write_record(buf, rec.id(), &protein, line_width)?;
+            }
+        }
+        Ok(true)
+    })?;
+    writer.flush()?;
+
+    info!("total sequences translated: {}", count);
+    Ok(())
+}
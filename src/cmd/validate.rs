@@ -0,0 +1,209 @@
+use crate::{
+    cmd::alphabet::Alphabet,
+    errors::FakitError,
+    report::{Cell, ReportFormat, ReportWriter},
+    utils::{CompressionFormat, file_reader, file_writer},
+};
+use log::{info, warn};
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+};
+
+const COLUMNS: [&str; 6] = ["file", "record", "id", "line", "issue", "detail"];
+
+// state of the record being read
+#[derive(Default)]
+struct RecordState {
+    index: usize,
+    id: String,
+    header_line: usize,
+    seq_len: usize,
+    bad_char: bool,
+    width: Option<usize>,
+    // last sequence line and its width, only the last line of a record can be shorter
+    last: Option<(usize, usize)>,
+    bad_width: bool,
+}
+
+struct Report<'a, W: Write> {
+    out: &'a mut W,
+    format: ReportFormat,
+    file: &'a str,
+    issues: usize,
+}
+
+impl<W: Write> Report<'_, W> {
+    fn add(
+        &mut self,
+        rec: &RecordState,
+        line: usize,
+        issue: &str,
+        detail: &str,
+    ) -> Result<(), FakitError> {
+        self.issues += 1;
+        let values = [
+            Cell::from(self.file),
+            Cell::from(rec.index),
+            Cell::from(rec.id.as_str()),
+            Cell::from(line),
+            Cell::from(issue),
+            Cell::from(detail),
+        ];
+        let row = COLUMNS.into_iter().zip(values).collect::<Vec<_>>();
+        self.format.write_row(self.out, &row)?;
+        Ok(())
+    }
+
+    // checks done when the whole record is read
+    fn finish(&mut self, rec: &RecordState) -> Result<(), FakitError> {
+        if rec.index == 0 {
+            return Ok(());
+        }
+        if rec.seq_len == 0 {
+            self.add(rec, rec.header_line, "empty_sequence", "no sequence data")?;
+        }
+        match (rec.width, rec.last) {
+            (Some(width), Some((line, last))) if last > width && !rec.bad_width => {
+                let detail = format!("line width {}, expect {}", last, width);
+                self.add(rec, line, "inconsistent_line_width", &detail)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn validate_fasta(
+    input: &[String],
+    alphabet: Alphabet,
+    header: bool,
+    format: ReportFormat,
+    output: Option<&String>,
+    compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
+) -> Result<(), FakitError> {
+    let mut allowed = [false; 256];
+    for &b in alphabet.letters().iter().chain(b"-") {
+        allowed[b as usize] = true;
+        allowed[b.to_ascii_lowercase() as usize] = true;
+    }
+
+    let mut out = ReportWriter::new(
+        file_writer(output, compression_level, threads, output_compression)?,
+        format,
+    );
+    if header {
+        format.write_header(&mut out, &COLUMNS)?;
+    }
+
+    let stdin = ["-"];
+    let inputs: Vec<&str> = if input.is_empty() {
+        stdin.to_vec()
+    } else {
+        input.iter().map(|x| x.as_str()).collect()
+    };
+
+    let mut total = 0usize;
+    for file in inputs {
+        let reader = if file == "-" {
+            file_reader::<&str>(None)?
+        } else {
+            file_reader(Some(file))?
+        };
+        let file = if file == "-" { "stdin" } else { file };
+        let mut report = Report {
+            out: &mut out,
+            format,
+            file,
+            issues: 0,
+        };
+
+        let mut ids: HashMap<String, usize> = HashMap::new();
+        let mut rec = RecordState::default();
+        let mut nrec = 0usize;
+        let mut no_header = false;
+        for (n, line) in reader.split(b'\n').enumerate() {
+            let line = line?;
+            let line = line.strip_suffix(b"\r").unwrap_or(&line);
+            let line_no = n + 1;
+
+            if let Some(header) = line.strip_prefix(b">") {
+                report.finish(&rec)?;
+                nrec += 1;
+                let id = String::from_utf8_lossy(header)
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or("")
+                    .to_string();
+                rec = RecordState {
+                    index: nrec,
+                    id,
+                    header_line: line_no,
+                    ..Default::default()
+                };
+                if rec.id.is_empty() {
+                    report.add(&rec, line_no, "empty_id", "header without sequence id")?;
+                } else if let Some(first) = ids.get(&rec.id) {
+                    let detail = format!("first seen at record #{}", first);
+                    report.add(&rec, line_no, "duplicate_id", &detail)?;
+                } else {
+                    ids.insert(rec.id.clone(), nrec);
+                }
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            if rec.index == 0 {
+                // report once for the data before the first header
+                if !no_header {
+                    no_header = true;
+                    report.add(
+                        &rec,
+                        line_no,
+                        "no_header",
+                        "sequence data before the first header",
+                    )?;
+                }
+                continue;
+            }
+
+            rec.seq_len += line.len();
+            if !rec.bad_char {
+                if let Some(pos) = line.iter().position(|&b| !allowed[b as usize]) {
+                    rec.bad_char = true;
+                    let detail = format!("character {:?} at column {}", line[pos] as char, pos + 1);
+                    report.add(&rec, line_no, "invalid_char", &detail)?;
+                }
+            }
+            // a line following another line must have the same width as the first line
+            match (rec.width, rec.last) {
+                (None, _) => rec.width = Some(line.len()),
+                (Some(width), Some((prev_line, prev))) if prev != width && !rec.bad_width => {
+                    rec.bad_width = true;
+                    let detail = format!("line width {}, expect {}", prev, width);
+                    report.add(&rec, prev_line, "inconsistent_line_width", &detail)?;
+                }
+                _ => {}
+            }
+            rec.last = Some((line_no, line.len()));
+        }
+        report.finish(&rec)?;
+
+        if report.issues > 0 {
+            warn!("{}: {} records, {} issues found", file, nrec, report.issues);
+        } else {
+            info!("{}: {} records, no issue found", file, nrec);
+        }
+        total += report.issues;
+    }
+    out.finish()?;
+
+    if total > 0 {
+        return Err(FakitError::ValidationFailed(total));
+    }
+    Ok(())
+}
@@ -1,32 +1,155 @@
+use std::path::PathBuf;
 use thiserror::Error;
 
+// process exit code for each class of error
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_INVALID_ARGUMENT: i32 = 2;
+pub const EXIT_INVALID_INPUT: i32 = 3;
+pub const EXIT_IO_FAILURE: i32 = 4;
+
 // define Error types
 #[derive(Debug, Error)]
 pub enum FakitError {
     #[error("Stdin not detected")]
     StdinNotDetected,
 
-    #[error("File not found")]
-    FileNotFound,
+    #[error("File not found: {}", .0.display())]
+    FileNotFound(PathBuf),
+
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    #[error("Invalid region: {0}")]
+    InvalidRegion(String),
+
+    #[error("Invalid fasta record #{index}: {source}")]
+    InvalidRecord {
+        // 1-based record index in the input stream
+        index: usize,
+        source: paraseq::fasta::Error,
+    },
+
+    #[error("Malformed fasta record #{index} in {} at line {line}: {reason}", .path.display())]
+    MalformedRecord {
+        path: PathBuf,
+        line: usize,
+        index: usize,
+        reason: String,
+    },
+
+    #[error("Validation failed with {0} issues found")]
+    ValidationFailed(usize),
 
-    #[error("Two pass error")]
+    #[error("Invalid bgzf block in {} at compressed offset {offset}", .path.display())]
+    InvalidBgzfBlock { path: PathBuf, offset: u64 },
+
+    #[error("Invalid kmer database {}: {reason}", .path.display())]
+    InvalidKmerDb { path: PathBuf, reason: String },
+
+    #[error("Unsupported compression format: {0}")]
+    UnsupportedFormat(String),
+
+    #[error("Stale index: {0}, rerun with option --rebuild")]
+    StaleIndex(String),
+
+    #[error("Two pass mode is not allowed when reading from stdin, please provide a file input")]
     TwoPassNotAllowedStdin,
 
-    #[error("Io error")]
-    IoError(#[from] std::io::Error),
+    #[error("Remote error: {0}")]
+    RemoteError(String),
+
+    #[error("Thread join error")]
+    ThreadJoinError,
 
-    #[error("Parse int error")]
+    #[error("Io error on {}: {source}", .path.display())]
+    FileIoError {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Io error: {0}")]
+    IoError(#[source] std::io::Error),
+
+    #[error("Parse int error: {0}")]
     ParseIntError(#[from] std::num::ParseIntError),
 
-    #[error("Regex Error")]
+    #[error("Regex error: {0}")]
     RegexError(#[from] regex::Error),
 
-    #[error("Utf8 error")]
+    #[error("Utf8 error: {0}")]
     Utf8Error(#[from] std::str::Utf8Error),
 
-    #[error("ParaseqFastaError")]
-    ParaseqFastaError(#[from] paraseq::fasta::Error),
+    #[error("Fasta parse error: {0}")]
+    ParaseqFastaError(#[source] paraseq::fasta::Error),
+}
+
+// readers can only raise io errors, so a FakitError found while reading, eg. a malformed record
+// in strict mode, is wrapped in one and taken out here
+impl From<std::io::Error> for FakitError {
+    fn from(e: std::io::Error) -> Self {
+        if !e.get_ref().is_some_and(|inner| inner.is::<FakitError>()) {
+            return FakitError::IoError(e);
+        }
+        let kind = e.kind();
+        match e.into_inner().map(|inner| inner.downcast::<FakitError>()) {
+            Some(Ok(inner)) => *inner,
+            Some(Err(inner)) => FakitError::IoError(std::io::Error::new(kind, inner)),
+            None => FakitError::IoError(kind.into()),
+        }
+    }
+}
+
+impl From<paraseq::fasta::Error> for FakitError {
+    fn from(e: paraseq::fasta::Error) -> Self {
+        match e {
+            paraseq::fasta::Error::Io(e) => e.into(),
+            e => FakitError::ParaseqFastaError(e),
+        }
+    }
+}
+
+impl FakitError {
+    /// exit code of the error class, so a caller can tell bad arguments, bad input and io failure apart
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FakitError::StdinNotDetected
+            | FakitError::InvalidArgument(_)
+            | FakitError::TwoPassNotAllowedStdin
+            | FakitError::RegexError(_) => EXIT_INVALID_ARGUMENT,
+            FakitError::InvalidRegion(_)
+            | FakitError::InvalidRecord { .. }
+            | FakitError::MalformedRecord { .. }
+            | FakitError::ValidationFailed(_)
+            | FakitError::InvalidBgzfBlock { .. }
+            | FakitError::InvalidKmerDb { .. }
+            | FakitError::UnsupportedFormat(_)
+            | FakitError::StaleIndex(_)
+            | FakitError::ParseIntError(_)
+            | FakitError::Utf8Error(_) => EXIT_INVALID_INPUT,
+            FakitError::ParaseqFastaError(_) => EXIT_INVALID_INPUT,
+            FakitError::FileIoError { source, .. } | FakitError::IoError(source) => {
+                io_exit_code(source)
+            }
+            FakitError::FileNotFound(_) | FakitError::RemoteError(_) => EXIT_IO_FAILURE,
+            FakitError::ThreadJoinError => EXIT_FAILURE,
+        }
+    }
+
+    // shift the record index of an error found in a batch by the number of records before it
+    pub fn with_record_offset(self, offset: usize) -> Self {
+        match self {
+            FakitError::InvalidRecord { index, source } => FakitError::InvalidRecord {
+                index: index + offset,
+                source,
+            },
+            e => e,
+        }
+    }
+}
 
-    #[error("ProcessError")]
-    ProcessError(#[from] paraseq::parallel::ProcessError),
+// an io error raised by a reader may carry the FakitError that caused it
+fn io_exit_code(e: &std::io::Error) -> i32 {
+    e.get_ref()
+        .and_then(|inner| inner.downcast_ref::<FakitError>())
+        .map_or(EXIT_IO_FAILURE, FakitError::exit_code)
 }
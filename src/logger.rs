@@ -27,6 +27,8 @@ pub fn logger<P: AsRef<Path>>(
     };
 
     let mut builder = Builder::from_default_env();
+    // http client logs every request at debug level, keep it quiet under default verbosity
+    builder.filter(Some("ureq"), level.min(LevelFilter::Info));
     builder.format(|buf, record| {
         let mut style = buf.style();
         match record.level() {
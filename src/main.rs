@@ -1,36 +1,93 @@
 use anyhow::{Error, Ok};
 use clap::Parser;
-use log::info;
-use std::time::Instant;
+use log::{error, info};
+use std::{process, time::Instant};
 
 mod cli;
 use cli::*;
 mod logger;
 use logger::*;
 mod errors;
+use errors::{EXIT_FAILURE, EXIT_IO_FAILURE, FakitError};
 
 mod cmd;
+mod remote;
+mod report;
+use report::ReportFormat;
 mod utils;
 use cmd::{
-    codon::*, fa2fq::*, faidx::*, flatten::*, grep::*, kmer::*, range::*, rename::*, reverse::*,
-    search::*, seq::*, shuffle::*, size::*, slide::*, sort::*, split::*, split2::*, stat::*,
-    subfa::*, tail::*, top::*,
+    amplicon::*, codon::*, fa2fq::*, faidx::*, flatten::*, grep::*, kmer::*, kmerdb::*, orf::*,
+    range::*, rename::*, reverse::*, rmdup::*, search::*, seq::*, shuffle::*, size::*, slide::*,
+    sort::*, split::*, split2::*, stat::*, subfa::*, tail::*, top::*, translate::*, validate::*,
 };
 
-fn main() -> Result<(), Error> {
+fn main() {
     let args = cli::Args::parse();
-    logger(args.verbose, args.logfile, args.quiet)?;
+    if let Err(e) = logger(args.verbose, args.logfile.as_ref(), args.quiet) {
+        eprintln!("Error: {:#}", e);
+        process::exit(EXIT_IO_FAILURE);
+    }
     let start = Instant::now();
     info!("version: {}", env!("CARGO_PKG_AUTHORS"));
 
+    if let Err(e) = run(args) {
+        // error message is always shown, even if logging is turned off
+        if log::log_enabled!(log::Level::Error) {
+            error!("{}", error_message(&e));
+        } else {
+            eprintln!("Error: {}", error_message(&e));
+        }
+        process::exit(exit_code(&e));
+    }
+    info!("time elapsed is: {:?}", start.elapsed());
+}
+
+// join the error chain, a cause already shown in the message of outer error is skipped
+fn error_message(e: &Error) -> String {
+    let mut msg = String::new();
+    for cause in e.chain() {
+        let text = cause.to_string();
+        if msg.contains(&text) {
+            continue;
+        }
+        if !msg.is_empty() {
+            msg.push_str(": ");
+        }
+        msg.push_str(&text);
+    }
+    msg
+}
+
+// the first FakitError in the error chain decides the exit code
+fn exit_code(e: &Error) -> i32 {
+    e.chain()
+        .find_map(|cause| {
+            cause
+                .downcast_ref::<FakitError>()
+                .map(FakitError::exit_code)
+                .or_else(|| {
+                    cause
+                        .downcast_ref::<std::io::Error>()
+                        .map(|_| EXIT_IO_FAILURE)
+                })
+        })
+        .unwrap_or(EXIT_FAILURE)
+}
+
+fn run(args: Args) -> Result<(), Error> {
+    let format = args.format.unwrap_or(ReportFormat::Tsv);
     match args.command {
         Subcli::topn { input, num, output } => {
             top_n_records(
                 num,
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 output.as_ref(),
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::tail {
@@ -41,11 +98,15 @@ fn main() -> Result<(), Error> {
         } => {
             tail_n_records(
                 num,
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 two_pass,
                 output.as_ref(),
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::fa2fq {
@@ -55,37 +116,101 @@ fn main() -> Result<(), Error> {
             output,
         } => {
             fake_quality(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 qual,
                 keep,
                 output.as_ref(),
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::faidx {
             input,
             region,
+            region_file,
+            name_format,
+            rebuild,
+            on_error,
             output,
         } => {
             faidx_fasta(
                 input.as_ref(),
                 region,
+                region_file.as_ref(),
+                name_format.as_deref(),
+                rebuild,
+                on_error,
                 output.as_ref(),
+                args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::kmer {
             input,
             size,
-            header,
+            canonical,
+            min_count,
+            max_count,
+            sort,
+            hist,
+            dump,
             out,
         } => {
             kmer_count(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 size,
-                header,
+                canonical,
+                min_count,
+                max_count,
+                sort,
+                hist,
+                dump.as_ref(),
+                args.header,
+                format,
+                out.as_ref(),
+                args.compression_level,
+                args.threads,
+                args.output_compression,
+            )?;
+        }
+        Subcli::kmer_query {
+            db,
+            input,
+            list,
+            intersect,
+            subtract,
+            union,
+            dump,
+            out,
+        } => {
+            let set = if let Some(path) = intersect.as_ref() {
+                Some((KmerSet::Intersect, path))
+            } else if let Some(path) = subtract.as_ref() {
+                Some((KmerSet::Subtract, path))
+            } else {
+                union.as_ref().map(|path| (KmerSet::Union, path))
+            };
+            kmer_query(
+                &db,
+                &input,
+                args.tag_source,
+                args.strict,
+                list.as_ref(),
+                set,
+                dump.as_ref(),
+                args.header,
+                format,
                 out.as_ref(),
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::flatten {
@@ -94,18 +219,26 @@ fn main() -> Result<(), Error> {
             gap,
             len,
             gc,
+            alphabet,
             sep,
             output,
         } => {
             flatten_fa(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 keep,
                 gap,
                 len,
                 gc,
+                alphabet,
                 sep,
+                !args.no_header,
+                args.format,
                 output.as_ref(),
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::range {
@@ -115,12 +248,16 @@ fn main() -> Result<(), Error> {
             out,
         } => {
             range_fasta(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 skip,
                 take,
                 out.as_ref(),
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::rename {
@@ -130,21 +267,39 @@ fn main() -> Result<(), Error> {
             output,
         } => {
             rename_fa(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 keep,
                 prefix,
                 output.as_ref(),
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
-        Subcli::reverse { input, rev, out } => {
+        Subcli::reverse {
+            input,
+            rev,
+            comp,
+            rna,
+            tag,
+            out,
+        } => {
             reverse_comp_seq(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 out.as_ref(),
                 rev,
+                comp,
+                rna,
+                tag.as_deref(),
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::window {
@@ -152,35 +307,67 @@ fn main() -> Result<(), Error> {
             wind,
             step,
             keep,
+            alphabet,
             output,
         } => {
             sliding_window(
                 step,
                 wind,
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 output.as_ref(),
                 keep,
+                alphabet,
+                !args.no_header,
+                args.format,
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::grep {
             input,
             pat,
+            seq_pattern,
             name,
+            desc,
             seq,
+            and,
+            degenerate,
             ignore,
+            invert,
+            count,
+            id_file,
+            full_header,
+            list_order,
+            unmatched,
             output,
         } => {
             grep_fasta(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 output.as_ref(),
-                &pat,
+                pat.as_deref(),
+                seq_pattern.as_deref(),
                 ignore,
                 name,
+                desc,
                 seq,
+                and,
+                degenerate,
+                invert,
+                count,
+                id_file.as_ref(),
+                full_header,
+                list_order,
+                unmatched.as_ref(),
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::seq {
@@ -192,20 +379,26 @@ fn main() -> Result<(), Error> {
             max,
             gc_min,
             gc_max,
+            alphabet,
             out,
         } => {
             seq_fa(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 lower,
                 upper,
                 min,
                 max,
                 gc_min,
                 gc_max,
+                alphabet,
                 sequence,
                 out.as_ref(),
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::sort {
@@ -218,7 +411,9 @@ fn main() -> Result<(), Error> {
             out,
         } => {
             sort_fasta(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 name,
                 seq,
                 gc,
@@ -227,15 +422,81 @@ fn main() -> Result<(), Error> {
                 out.as_ref(),
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::shuffle { input, seed, out } => {
             shuffle_fasta(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 seed,
                 out.as_ref(),
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
+            )?;
+        }
+        Subcli::amplicon {
+            input,
+            forward,
+            reverse,
+            primers,
+            mismatch,
+            min_len,
+            max_len,
+            trim_primers,
+            report,
+            output,
+        } => {
+            amplicon_fasta(
+                &input,
+                args.tag_source,
+                args.strict,
+                forward.as_deref(),
+                reverse.as_deref(),
+                primers.as_ref(),
+                mismatch,
+                min_len,
+                max_len,
+                trim_primers,
+                output.as_ref(),
+                report.as_ref(),
+                !args.no_header,
+                format,
+                args.width,
+                args.compression_level,
+                args.threads,
+                args.output_compression,
+            )?;
+        }
+        Subcli::rmdup {
+            input,
+            key,
+            canonical,
+            digest,
+            removed,
+            groups,
+            out,
+        } => {
+            rmdup_fasta(
+                &input,
+                args.tag_source,
+                args.strict,
+                key,
+                canonical,
+                digest,
+                out.as_ref(),
+                removed.as_ref(),
+                groups.as_ref(),
+                !args.no_header,
+                format,
+                args.width,
+                args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::search {
@@ -243,33 +504,51 @@ fn main() -> Result<(), Error> {
             pat,
             keep,
             ignore_case,
-            Header,
+            motif,
+            forward,
+            mismatch,
+            edit,
             output,
         } => {
             search_fa(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 output.as_ref(),
                 &pat,
                 ignore_case,
-                Header,
+                args.header,
                 keep,
+                motif,
+                forward,
+                mismatch,
+                edit,
+                format,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::size {
             input,
             all,
+            alphabet,
             keep,
-            noheader,
             output,
         } => {
             size_fasta(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 all,
+                alphabet,
                 keep,
-                noheader,
+                !args.no_header,
+                format,
                 output.as_ref(),
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::subfa {
@@ -280,21 +559,61 @@ fn main() -> Result<(), Error> {
             output,
         } => {
             select_fasta(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 num,
                 seed,
                 two_pass,
                 output.as_ref(),
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
-        Subcli::summ { file, all, output } => {
+        Subcli::summ {
+            file,
+            all,
+            alphabet,
+            genome_size,
+            hist,
+            tabular,
+            output,
+        } => {
             let buf = file.iter().map(|x| x.as_str()).collect();
             if let Some(path) = output {
-                summary_fa(buf, all, Some(&path), args.compression_level)?;
+                summary_fa(
+                    buf,
+                    all,
+                    alphabet,
+                    genome_size,
+                    hist,
+                    tabular,
+                    args.strict,
+                    !args.no_header,
+                    format,
+                    Some(&path),
+                    args.compression_level,
+                    args.threads,
+                    args.output_compression,
+                )?;
             } else {
-                summary_fa(buf, all, None, args.compression_level)?;
+                summary_fa(
+                    buf,
+                    all,
+                    alphabet,
+                    genome_size,
+                    hist,
+                    tabular,
+                    args.strict,
+                    !args.no_header,
+                    format,
+                    None,
+                    args.compression_level,
+                    args.threads,
+                    args.output_compression,
+                )?;
             }
         }
         Subcli::split {
@@ -304,12 +623,16 @@ fn main() -> Result<(), Error> {
             outdir,
         } => {
             split_fa(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 ext,
                 keep,
                 outdir.as_ref(),
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
         Subcli::split2 {
@@ -321,7 +644,9 @@ fn main() -> Result<(), Error> {
             name,
         } => {
             split_chunk(
-                input.as_ref(),
+                &input,
+                args.tag_source,
+                args.strict,
                 num,
                 gzip,
                 bzip2,
@@ -329,13 +654,84 @@ fn main() -> Result<(), Error> {
                 &name,
                 args.width,
                 args.compression_level,
+                args.threads,
+                args.output_compression,
+            )?;
+        }
+        Subcli::validate {
+            input,
+            alphabet,
+            output,
+        } => {
+            validate_fasta(
+                &input,
+                alphabet,
+                !args.no_header,
+                format,
+                output.as_ref(),
+                args.compression_level,
+                args.threads,
+                args.output_compression,
+            )?;
+        }
+        Subcli::translate {
+            input,
+            table,
+            frame,
+            trim_stop,
+            init_start,
+            output,
+        } => {
+            translate_fasta(
+                &input,
+                args.tag_source,
+                args.strict,
+                table,
+                &frame,
+                trim_stop,
+                init_start,
+                output.as_ref(),
+                args.width,
+                args.compression_level,
+                args.threads,
+                args.output_compression,
             )?;
         }
-        Subcli::codon { name } => {
-            show_codon(name)?;
+        Subcli::orf {
+            input,
+            table,
+            min_len,
+            start,
+            nested,
+            partial,
+            protein,
+            bed,
+            gff,
+            output,
+        } => {
+            orf_fasta(
+                &input,
+                args.tag_source,
+                args.strict,
+                table,
+                min_len,
+                start,
+                nested,
+                partial,
+                protein,
+                output.as_ref(),
+                bed.as_ref(),
+                gff.as_ref(),
+                args.width,
+                args.compression_level,
+                args.threads,
+                args.output_compression,
+            )?;
+        }
+        Subcli::codon { name, table } => {
+            show_codon(name, table)?;
         }
     }
 
-    info!("time elapsed is: {:?}", start.elapsed());
     Ok(())
 }
@@ -0,0 +1,302 @@
+use crate::errors::FakitError;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{debug, info};
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+// bytes fetched by one http range request in random access mode
+const RANGE_CHUNK_SIZE: u64 = 256 * 1024;
+
+/// Returns true if the input is a http(s):// or s3:// url.
+pub fn is_remote(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://") || input.starts_with("s3://")
+}
+
+// credentials for signed s3 requests, read from the same environment variables as aws cli
+struct S3Credential {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+    region: String,
+}
+
+struct RemoteUrl {
+    url: String,
+    s3: Option<S3Credential>,
+}
+
+impl RemoteUrl {
+    // s3://bucket/key is resolved to path-style url under AWS_ENDPOINT_URL (eg. a local MinIO
+    // http://127.0.0.1:9000), or virtual-hosted-style url on amazon s3
+    fn parse(input: &str) -> Result<Self, FakitError> {
+        let Some(path) = input.strip_prefix("s3://") else {
+            return Ok(RemoteUrl {
+                url: input.to_string(),
+                s3: None,
+            });
+        };
+        let (bucket, key) = path
+            .split_once('/')
+            .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+            .ok_or_else(|| FakitError::RemoteError(format!("invalid s3 url: {}", input)))?;
+
+        let region = env::var("AWS_REGION")
+            .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| "us-east-1".to_string());
+        let url = match env::var("AWS_ENDPOINT_URL") {
+            Ok(endpoint) => format!(
+                "{}/{}/{}",
+                endpoint.trim_end_matches('/'),
+                bucket,
+                uri_encode(key)
+            ),
+            Err(_) => format!(
+                "https://{}.s3.{}.amazonaws.com/{}",
+                bucket,
+                region,
+                uri_encode(key)
+            ),
+        };
+        debug!("resolve {} to {}", input, url);
+
+        let s3 = match (
+            env::var("AWS_ACCESS_KEY_ID"),
+            env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            (Ok(access_key), Ok(secret_key)) => Some(S3Credential {
+                access_key,
+                secret_key,
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+                region,
+            }),
+            _ => None,
+        };
+
+        Ok(RemoteUrl { url, s3 })
+    }
+
+    fn request(&self, method: &str) -> Result<ureq::Request, FakitError> {
+        let req = ureq::request(method, &self.url);
+        match &self.s3 {
+            Some(cred) => sign_request(req, method, cred),
+            None => Ok(req),
+        }
+    }
+
+    fn call(&self, method: &str, range: Option<(u64, u64)>) -> Result<ureq::Response, FakitError> {
+        let mut req = self.request(method)?;
+        if let Some((start, end)) = range {
+            req = req.set("Range", &format!("bytes={}-{}", start, end));
+        }
+        // error message of http client contains the url
+        req.call()
+            .map_err(|e| FakitError::RemoteError(e.to_string()))
+    }
+}
+
+// aws signature version 4, the payload is not signed
+fn sign_request(
+    req: ureq::Request,
+    method: &str,
+    cred: &S3Credential,
+) -> Result<ureq::Request, FakitError> {
+    let invalid = |e: String| FakitError::RemoteError(format!("{}: {}", req.url(), e));
+    let url = req.request_url().map_err(|e| invalid(e.to_string()))?;
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host(), port),
+        None => url.host().to_string(),
+    };
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload = "UNSIGNED-PAYLOAD";
+
+    let mut headers = vec![
+        ("host", host.as_str()),
+        ("x-amz-content-sha256", payload),
+        ("x-amz-date", amz_date.as_str()),
+    ];
+    if let Some(token) = &cred.session_token {
+        headers.push(("x-amz-security-token", token.as_str()));
+    }
+    let canonical_headers = headers
+        .iter()
+        .map(|(k, v)| format!("{}:{}\n", k, v.trim()))
+        .collect::<String>();
+    let signed_headers = headers
+        .iter()
+        .map(|(k, _)| *k)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method,
+        url.path(),
+        canonical_headers,
+        signed_headers,
+        payload
+    );
+
+    let scope = format!("{}/{}/s3/aws4_request", date, cred.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut key = format!("AWS4{}", cred.secret_key).into_bytes();
+    for part in [date.as_str(), cred.region.as_str(), "s3", "aws4_request"] {
+        key = hmac_sha256(&key, part.as_bytes()).map_err(invalid)?;
+    }
+    let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()).map_err(invalid)?);
+
+    let mut req = req
+        .set("x-amz-content-sha256", payload)
+        .set("x-amz-date", &amz_date)
+        .set(
+            "Authorization",
+            &format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                cred.access_key, scope, signed_headers, signature
+            ),
+        );
+    if let Some(token) = &cred.session_token {
+        req = req.set("x-amz-security-token", token);
+    }
+    Ok(req)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).map_err(|e| e.to_string())?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn uri_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// Open a remote file as a stream, the whole body is read sequentially.
+pub fn open_stream(input: &str) -> Result<Box<dyn Read + Send>, FakitError> {
+    let remote = RemoteUrl::parse(input)?;
+    info!("reading from url: {}", remote.url);
+    let resp = remote.call("GET", None)?;
+    Ok(Box::new(resp.into_reader()))
+}
+
+/// Read the whole remote file into memory, used for small files such as .fai and .gzi index.
+pub fn read_to_end(input: &str) -> Result<Vec<u8>, FakitError> {
+    let mut buf = vec![];
+    open_stream(input)?.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// A seekable remote file, data is fetched by http range requests on demand.
+pub struct RemoteFile {
+    remote: RemoteUrl,
+    len: u64,
+    pos: u64,
+    buf: Vec<u8>,
+    buf_start: u64,
+}
+
+impl RemoteFile {
+    pub fn open(input: &str) -> Result<Self, FakitError> {
+        let remote = RemoteUrl::parse(input)?;
+        let resp = remote.call("HEAD", None)?;
+        if resp.header("Accept-Ranges") == Some("none") {
+            return Err(FakitError::RemoteError(format!(
+                "{}: server does not support range requests",
+                remote.url
+            )));
+        }
+        let len = resp
+            .header("Content-Length")
+            .and_then(|x| x.parse::<u64>().ok())
+            .ok_or_else(|| {
+                FakitError::RemoteError(format!("{}: unknown content length", remote.url))
+            })?;
+        info!("random access to url: {}, size: {}", remote.url, len);
+
+        Ok(RemoteFile {
+            remote,
+            len,
+            pos: 0,
+            buf: vec![],
+            buf_start: 0,
+        })
+    }
+
+    fn fetch(&mut self, start: u64) -> io::Result<()> {
+        let end = (start + RANGE_CHUNK_SIZE).min(self.len) - 1;
+        debug!("range request {}: bytes={}-{}", self.remote.url, start, end);
+        let resp = self
+            .remote
+            .call("GET", Some((start, end)))
+            .map_err(io::Error::other)?;
+        if resp.status() != 206 {
+            return Err(io::Error::other(format!(
+                "{}: server does not support range requests",
+                self.remote.url
+            )));
+        }
+        self.buf.clear();
+        resp.into_reader().read_to_end(&mut self.buf)?;
+        self.buf_start = start;
+        Ok(())
+    }
+}
+
+impl Read for RemoteFile {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || out.is_empty() {
+            return Ok(0);
+        }
+        let buf_end = self.buf_start + self.buf.len() as u64;
+        if self.pos < self.buf_start || self.pos >= buf_end {
+            self.fetch(self.pos)?;
+        }
+        let offset = (self.pos - self.buf_start) as usize;
+        let n = out.len().min(self.buf.len() - offset);
+        out[..n].copy_from_slice(&self.buf[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for RemoteFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        match new_pos {
+            Some(n) => {
+                self.pos = n;
+                Ok(n)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )),
+        }
+    }
+}
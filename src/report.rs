@@ -0,0 +1,263 @@
+use clap::ValueEnum;
+use serde_json::{Map, Number, Value};
+use std::io::{self, Write};
+
+/// output format of tabular reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// tab separated values
+    Tsv,
+    /// comma separated values
+    Csv,
+    /// a json array of objects
+    Json,
+    /// one json object per line
+    Jsonl,
+}
+
+/// A typed cell of a report row.
+pub enum Cell {
+    Str(String),
+    Int(u64),
+    // value and decimal places
+    Float(f64, usize),
+    Null,
+}
+
+impl From<&str> for Cell {
+    fn from(x: &str) -> Self {
+        Cell::Str(x.to_string())
+    }
+}
+
+impl From<usize> for Cell {
+    fn from(x: usize) -> Self {
+        Cell::Int(x as u64)
+    }
+}
+
+impl From<u64> for Cell {
+    fn from(x: u64) -> Self {
+        Cell::Int(x)
+    }
+}
+
+impl Cell {
+    pub fn text(&self) -> String {
+        match self {
+            Cell::Str(x) => x.clone(),
+            Cell::Int(x) => x.to_string(),
+            Cell::Float(x, p) => format!("{:.*}", p, x),
+            Cell::Null => "NA".to_string(),
+        }
+    }
+
+    fn json(&self) -> Value {
+        match self {
+            Cell::Str(x) => Value::String(x.clone()),
+            Cell::Int(x) => Value::from(*x),
+            Cell::Float(x, p) => {
+                let scale = 10f64.powi(*p as i32);
+                Number::from_f64((x * scale).round() / scale).map_or(Value::Null, Value::Number)
+            }
+            Cell::Null => Value::Null,
+        }
+    }
+}
+
+// quote a csv field when needed
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl ReportFormat {
+    /// Write the header line for tsv and csv, nothing for json.
+    pub fn write_header<W: Write + ?Sized>(
+        &self,
+        writer: &mut W,
+        columns: &[&str],
+    ) -> io::Result<()> {
+        match self {
+            ReportFormat::Tsv => writeln!(writer, "{}", columns.join("\t")),
+            ReportFormat::Csv => {
+                let fields = columns.iter().map(|x| csv_field(x)).collect::<Vec<_>>();
+                writeln!(writer, "{}", fields.join(","))
+            }
+            ReportFormat::Json | ReportFormat::Jsonl => Ok(()),
+        }
+    }
+
+    /// Write a row as one line, json rows are objects keyed by column name.
+    pub fn write_row<W: Write + ?Sized, K: AsRef<str>>(
+        &self,
+        writer: &mut W,
+        row: &[(K, Cell)],
+    ) -> io::Result<()> {
+        match self {
+            ReportFormat::Tsv => {
+                let fields = row.iter().map(|(_, v)| v.text()).collect::<Vec<_>>();
+                writeln!(writer, "{}", fields.join("\t"))
+            }
+            ReportFormat::Csv => {
+                let fields = row
+                    .iter()
+                    .map(|(_, v)| csv_field(&v.text()))
+                    .collect::<Vec<_>>();
+                writeln!(writer, "{}", fields.join(","))
+            }
+            ReportFormat::Json | ReportFormat::Jsonl => {
+                let obj = row
+                    .iter()
+                    .map(|(k, v)| (k.as_ref().to_string(), v.json()))
+                    .collect::<Map<String, Value>>();
+                serde_json::to_writer(&mut *writer, &obj)?;
+                writer.write_all(b"\n")
+            }
+        }
+    }
+}
+
+/// Writer of a report, in json format the rows written line by line are joined into an array,
+/// so rows can be formatted in parallel with ReportFormat::write_row.
+pub struct ReportWriter<W: Write> {
+    inner: W,
+    format: ReportFormat,
+    started: bool,
+    // the newline at the end of last json row, replaced by the separator of next row
+    pending_newline: bool,
+}
+
+impl<W: Write> ReportWriter<W> {
+    pub fn new(inner: W, format: ReportFormat) -> Self {
+        ReportWriter {
+            inner,
+            format,
+            started: false,
+            pending_newline: false,
+        }
+    }
+
+    /// Close the json array and flush the inner writer.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.format == ReportFormat::Json {
+            if self.started {
+                self.inner.write_all(b"\n]\n")?;
+            } else {
+                self.inner.write_all(b"[]\n")?;
+            }
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for ReportWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.format != ReportFormat::Json {
+            return self.inner.write(buf);
+        }
+        let mut rest = buf;
+        while !rest.is_empty() {
+            if !self.started {
+                self.inner.write_all(b"[\n")?;
+                self.started = true;
+            } else if self.pending_newline {
+                self.inner.write_all(b",\n")?;
+                self.pending_newline = false;
+            }
+            match rest.iter().position(|x| *x == b'\n') {
+                Some(i) => {
+                    self.inner.write_all(&rest[..i])?;
+                    self.pending_newline = true;
+                    rest = &rest[i + 1..];
+                }
+                None => {
+                    self.inner.write_all(rest)?;
+                    rest = &[];
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // header and rows written through a ReportWriter, rows are formatted apart as in workers
+    fn report(format: ReportFormat, rows: &[Vec<(&str, Cell)>]) -> String {
+        let mut out = vec![];
+        let mut w = ReportWriter::new(&mut out, format);
+        if let Some(row) = rows.first() {
+            let columns = row.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+            format.write_header(&mut w, &columns).unwrap();
+        }
+        for row in rows {
+            let mut buf = vec![];
+            format.write_row(&mut buf, row).unwrap();
+            w.write_all(&buf).unwrap();
+        }
+        w.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn rows() -> Vec<Vec<(&'static str, Cell)>> {
+        vec![
+            vec![
+                ("id", Cell::from("seq1 a,b")),
+                ("length", Cell::from(10usize)),
+                ("gc", Cell::Float(0.123456, 4)),
+            ],
+            vec![
+                ("id", Cell::from("say \"hi\"\tnow")),
+                ("length", Cell::from(0usize)),
+                ("gc", Cell::Null),
+            ],
+        ]
+    }
+
+    #[test]
+    fn tsv_report() {
+        assert_eq!(
+            report(ReportFormat::Tsv, &rows()),
+            "id\tlength\tgc\nseq1 a,b\t10\t0.1235\nsay \"hi\"\tnow\t0\tNA\n"
+        );
+    }
+
+    #[test]
+    fn csv_quoting() {
+        assert_eq!(
+            report(ReportFormat::Csv, &rows()),
+            "id,length,gc\n\"seq1 a,b\",10,0.1235\n\"say \"\"hi\"\"\tnow\",0,NA\n"
+        );
+        assert_eq!(csv_field("a\nb"), "\"a\nb\"");
+        assert_eq!(csv_field("plain"), "plain");
+    }
+
+    #[test]
+    fn json_escaping() {
+        let jsonl = report(ReportFormat::Jsonl, &rows());
+        assert_eq!(
+            jsonl,
+            "{\"id\":\"seq1 a,b\",\"length\":10,\"gc\":0.1235}\n\
+             {\"id\":\"say \\\"hi\\\"\\tnow\",\"length\":0,\"gc\":null}\n"
+        );
+        // a json array of the same objects, valid json as a whole
+        let json = report(ReportFormat::Json, &rows());
+        let value: Value = serde_json::from_str(&json).unwrap();
+        let expect = jsonl
+            .lines()
+            .map(|x| serde_json::from_str::<Value>(x).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(value, Value::Array(expect));
+        assert_eq!(report(ReportFormat::Json, &[]), "[]\n");
+    }
+}
@@ -1,30 +1,46 @@
-use crate::errors::FakitError;
-use log::{error, info};
+use crate::{errors::FakitError, remote};
+use clap::ValueEnum;
+use log::{info, warn};
+use noodles::bgzf;
+use paraseq::fasta::{Reader, RecordSet, RefRecord};
+use parking_lot::Mutex;
 use std::{
+    collections::{BTreeMap, VecDeque},
     fs::File,
     io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write, prelude::*, stdin},
-    path::Path,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Arc, mpsc},
+    thread,
 };
 
 const GZ_MAGIC: [u8; 3] = [0x1f, 0x8b, 0x08];
+// gzip header with FEXTRA flag set, followed by the BGZF "BC" subfield at offset 12
+const BGZF_MAGIC: [u8; 4] = [0x1f, 0x8b, 0x08, 0x04];
+const BGZF_SUBFIELD: [u8; 2] = [0x42, 0x43];
 const BZ_MAGIC: [u8; 3] = [0x42, 0x5a, 0x68];
 const XZ_MAGIC: [u8; 6] = [0xfd, 0x37, 0x7a, 0x58, 0x5A, 0x00];
 const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
-const MAGIC_MAX_LEN: usize = 6;
+const MAGIC_MAX_LEN: usize = 16;
 const BUFF_SIZE: usize = 1024 * 1024;
 
-#[derive(Debug, Clone, Copy)]
-enum CompressionFormat {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompressionFormat {
+    /// blocked gzip, can be indexed by faidx
+    Bgzf,
     Gzip,
     Bzip2,
     Xz,
     Zstd,
+    /// uncompressed
+    #[value(name = "none")]
     Plain,
 }
 
 impl CompressionFormat {
     fn magic_number(&self) -> &[u8] {
         match self {
+            CompressionFormat::Bgzf => &BGZF_MAGIC,
             CompressionFormat::Gzip => &GZ_MAGIC,
             CompressionFormat::Bzip2 => &BZ_MAGIC,
             CompressionFormat::Xz => &XZ_MAGIC,
@@ -35,24 +51,45 @@ impl CompressionFormat {
 
     fn is_match(&self, buffer: &[u8]) -> bool {
         let magic = self.magic_number();
-        buffer.len() >= magic.len() && buffer[..magic.len()] == *magic
+        let matched = buffer.len() >= magic.len() && buffer[..magic.len()] == *magic;
+        match self {
+            CompressionFormat::Bgzf => {
+                matched && buffer.len() >= 14 && buffer[12..14] == BGZF_SUBFIELD
+            }
+            _ => matched,
+        }
     }
 }
 
+// open a file for reading, the error keeps the file path
+pub fn open_file<P: AsRef<Path>>(file_name: P) -> Result<File, FakitError> {
+    let path = file_name.as_ref();
+    File::open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => FakitError::FileNotFound(path.to_path_buf()),
+        _ => FakitError::FileIoError {
+            path: path.to_path_buf(),
+            source: e,
+        },
+    })
+}
+
 fn magic_num<P: AsRef<Path> + Copy>(file_name: P) -> Result<[u8; MAGIC_MAX_LEN], FakitError> {
     let mut buffer: [u8; MAGIC_MAX_LEN] = [0; MAGIC_MAX_LEN];
-    let mut fp = File::open(file_name)?;
+    let mut fp = open_file(file_name)?;
     let _ = fp.read(&mut buffer)?;
     Ok(buffer)
 }
 
-fn detect_compression<P: AsRef<Path> + Copy>(
+pub fn detect_compression<P: AsRef<Path> + Copy>(
     file_name: P,
 ) -> Result<CompressionFormat, FakitError> {
     let buffer = magic_num(file_name)?;
     let path = file_name.as_ref();
 
-    if CompressionFormat::Gzip.is_match(&buffer) || path.extension().is_some_and(|ext| ext == "gz")
+    if CompressionFormat::Bgzf.is_match(&buffer) {
+        Ok(CompressionFormat::Bgzf)
+    } else if CompressionFormat::Gzip.is_match(&buffer)
+        || path.extension().is_some_and(|ext| ext == "gz")
     {
         Ok(CompressionFormat::Gzip)
     } else if CompressionFormat::Bzip2.is_match(&buffer)
@@ -72,99 +109,677 @@ fn detect_compression<P: AsRef<Path> + Copy>(
     }
 }
 
+// detect by magic number only, used when there is no file name, eg. reading from stdin
+pub fn detect_magic(buffer: &[u8]) -> CompressionFormat {
+    [
+        CompressionFormat::Bgzf,
+        CompressionFormat::Gzip,
+        CompressionFormat::Bzip2,
+        CompressionFormat::Xz,
+        CompressionFormat::Zstd,
+    ]
+    .into_iter()
+    .find(|format| format.is_match(buffer))
+    .unwrap_or(CompressionFormat::Plain)
+}
+
+fn decoded_reader<R>(
+    inner: R,
+    format: CompressionFormat,
+) -> Result<Box<dyn BufRead + Send>, FakitError>
+where
+    R: Read + Send + 'static,
+{
+    match format {
+        CompressionFormat::Bgzf | CompressionFormat::Gzip => Ok(Box::new(
+            BufReader::with_capacity(BUFF_SIZE, flate2::read::MultiGzDecoder::new(inner)),
+        )),
+        CompressionFormat::Bzip2 => Ok(Box::new(BufReader::with_capacity(
+            BUFF_SIZE,
+            bzip2::read::MultiBzDecoder::new(inner),
+        ))),
+        CompressionFormat::Xz => Ok(Box::new(BufReader::with_capacity(
+            BUFF_SIZE,
+            xz2::read::XzDecoder::new_multi_decoder(inner),
+        ))),
+        CompressionFormat::Zstd => Ok(Box::new(BufReader::with_capacity(
+            BUFF_SIZE,
+            zstd::stream::read::Decoder::new(inner)?,
+        ))),
+        CompressionFormat::Plain => Ok(Box::new(BufReader::with_capacity(BUFF_SIZE, inner))),
+    }
+}
+
 pub fn file_reader<P>(file_in: Option<P>) -> Result<Box<dyn BufRead + Send>, FakitError>
 where
     P: AsRef<Path> + Copy,
 {
     if let Some(file_name) = file_in {
-        info!("reading from file: {}", file_name.as_ref().display());
-        let fp = File::open(file_name)?;
-
-        match detect_compression(file_name)? {
-            CompressionFormat::Gzip => Ok(Box::new(BufReader::with_capacity(
-                BUFF_SIZE,
-                flate2::read::MultiGzDecoder::new(fp),
-            ))),
-            CompressionFormat::Bzip2 => Ok(Box::new(BufReader::with_capacity(
-                BUFF_SIZE,
-                bzip2::read::MultiBzDecoder::new(fp),
-            ))),
-            CompressionFormat::Xz => Ok(Box::new(BufReader::with_capacity(
-                BUFF_SIZE,
-                xz2::read::XzDecoder::new_multi_decoder(fp),
-            ))),
-            CompressionFormat::Zstd => Ok(Box::new(BufReader::with_capacity(
-                BUFF_SIZE,
-                zstd::stream::read::Decoder::new(fp)?,
-            ))),
-            CompressionFormat::Plain => Ok(Box::new(BufReader::with_capacity(BUFF_SIZE, fp))),
+        if let Some(url) = file_name.as_ref().to_str().filter(|x| remote::is_remote(x)) {
+            let (head, stream) = peek_magic(remote::open_stream(url)?)?;
+            let format = detect_magic(&head);
+            info!("compression format: {:?}", format);
+            let reader = decoded_reader(io::Cursor::new(head).chain(stream), format)?;
+            return Ok(Box::new(ContextReader::new(reader, url)));
         }
+        info!("reading from file: {}", file_name.as_ref().display());
+        let fp = open_file(file_name)?;
+        let reader = decoded_reader(fp, detect_compression(file_name)?)?;
+        Ok(Box::new(ContextReader::new(reader, file_name)))
     } else {
         if stdin().is_terminal() {
-            error!("{}", FakitError::StdinNotDetected);
-            std::process::exit(1);
+            return Err(FakitError::StdinNotDetected);
+        }
+        let (head, stdin) = peek_magic(io::stdin())?;
+        let format = detect_magic(&head);
+        info!("reading from stdin, compression format: {:?}", format);
+        decoded_reader(io::Cursor::new(head).chain(stdin), format)
+    }
+}
+
+// add file path to io errors raised while reading and decompressing the file
+struct ContextReader {
+    inner: Box<dyn BufRead + Send>,
+    path: PathBuf,
+}
+
+impl ContextReader {
+    fn new<P: AsRef<Path>>(inner: Box<dyn BufRead + Send>, path: P) -> Self {
+        ContextReader {
+            inner,
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+fn with_path(path: &Path, e: io::Error) -> io::Error {
+    let kind = e.kind();
+    let path = path.to_path_buf();
+    io::Error::new(kind, FakitError::FileIoError { path, source: e })
+}
+
+impl Read for ContextReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(|e| with_path(&self.path, e))
+    }
+}
+
+impl BufRead for ContextReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf().map_err(|e| with_path(&self.path, e))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt)
+    }
+}
+
+// peek the leading bytes of a stream, the caller puts them back in front of the stream
+pub fn peek_magic<R: Read>(mut reader: R) -> Result<(Vec<u8>, R), FakitError> {
+    let mut head = Vec::with_capacity(MAGIC_MAX_LEN);
+    reader
+        .by_ref()
+        .take(MAGIC_MAX_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok((head, reader))
+}
+
+/// Returns true if stdin is one of the inputs, no input or "-" means reading from stdin.
+pub fn reads_stdin<P: AsRef<Path>>(inputs: &[P]) -> bool {
+    inputs.is_empty() || inputs.iter().any(|x| x.as_ref() == Path::new("-"))
+}
+
+/// Concatenate several inputs into one fasta stream, each input is decompressed by its own format.
+/// If tag_source is set, sequence id is prefixed with the input file name, eg. >a.fa.gz|seq1
+/// If strict is set, a malformed line fails the reading with the record number.
+pub fn multi_reader<P: AsRef<Path>>(
+    inputs: &[P],
+    tag_source: bool,
+    strict: bool,
+) -> Result<Box<dyn BufRead + Send>, FakitError> {
+    let mut inputs = inputs
+        .iter()
+        .map(|x| x.as_ref().to_path_buf())
+        .collect::<VecDeque<PathBuf>>();
+    if inputs.is_empty() {
+        inputs.push_back(PathBuf::from("-"));
+    }
+    // inputs are opened one by one, check local files before any output is written
+    for path in inputs.iter().filter(|x| x.as_os_str() != "-") {
+        if !path.to_str().is_some_and(remote::is_remote) && !path.exists() {
+            return Err(FakitError::FileNotFound(path.clone()));
+        }
+    }
+    if inputs.len() == 1 && !tag_source && !strict {
+        return file_reader(inputs.front().filter(|x| x.as_os_str() != "-"));
+    }
+    info!("reading {} inputs as one stream", inputs.len());
+
+    let reader = MultiReader {
+        inputs,
+        current: None,
+        tag: None,
+        tag_source,
+        strict,
+        path: PathBuf::new(),
+        line_no: 0,
+        nrec: 0,
+        in_record: false,
+        line: Vec::new(),
+        pos: 0,
+        last_byte: None,
+    };
+    Ok(Box::new(BufReader::with_capacity(BUFF_SIZE, reader)))
+}
+
+struct MultiReader {
+    inputs: VecDeque<PathBuf>,
+    current: Option<Box<dyn BufRead + Send>>,
+    // header prefix of current input, eg. ">a.fa|"
+    tag: Option<Vec<u8>>,
+    tag_source: bool,
+    strict: bool,
+    // current input and position of the line, record number is counted over all inputs
+    path: PathBuf,
+    line_no: usize,
+    nrec: usize,
+    in_record: bool,
+    // pending bytes of a line in line mode
+    line: Vec<u8>,
+    pos: usize,
+    last_byte: Option<u8>,
+}
+
+impl MultiReader {
+    // inputs are opened lazily, returns false when all inputs are consumed
+    fn next_input(&mut self) -> io::Result<bool> {
+        let Some(path) = self.inputs.pop_front() else {
+            return Ok(false);
+        };
+        let is_stdin = path.as_os_str() == "-";
+        let reader =
+            file_reader(if is_stdin { None } else { Some(&path) }).map_err(io::Error::other)?;
+        if self.tag_source {
+            let name = match path.file_name() {
+                _ if is_stdin => "stdin".to_string(),
+                Some(name) => name.to_string_lossy().to_string(),
+                None => path.to_string_lossy().to_string(),
+            };
+            self.tag = Some(format!(">{}|", name).into_bytes());
+        }
+        self.current = Some(reader);
+        self.path = path;
+        self.line_no = 0;
+        self.in_record = false;
+        Ok(true)
+    }
+
+    // check the line just read in strict mode
+    fn check_line(&mut self) -> io::Result<()> {
+        self.line_no += 1;
+        let line = self.line.trim_ascii_end();
+        let reason = if line.first() == Some(&b'>') {
+            self.nrec += 1;
+            self.in_record = true;
+            line[1..]
+                .split(|b| b.is_ascii_whitespace())
+                .all(|x| x.is_empty())
+                .then(|| "empty sequence id".to_string())
+        } else if line.is_empty() {
+            None
+        } else if !self.in_record {
+            Some("sequence data before the first header".to_string())
+        } else {
+            line.iter()
+                .find(|b| !(b.is_ascii_alphabetic() || b"*-.".contains(b)))
+                .map(|b| format!("invalid character {:?} in sequence", *b as char))
+        };
+
+        match reason {
+            Some(reason) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                FakitError::MalformedRecord {
+                    path: self.path.clone(),
+                    line: self.line_no,
+                    index: self.nrec.max(1),
+                    reason,
+                },
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Read for MultiReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.pos < self.line.len() {
+                let n = out.len().min(self.line.len() - self.pos);
+                out[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+                self.pos += n;
+                return Ok(n);
+            }
+            let Some(reader) = self.current.as_mut() else {
+                if self.next_input()? {
+                    continue;
+                }
+                return Ok(0);
+            };
+
+            self.line.clear();
+            self.pos = 0;
+            let n = if self.tag_source || self.strict {
+                let n = reader.read_until(b'\n', &mut self.line)?;
+                if self.strict && n > 0 {
+                    self.check_line()?;
+                }
+                if let Some(tag) = self
+                    .tag
+                    .as_ref()
+                    .filter(|_| self.line.first() == Some(&b'>'))
+                {
+                    self.line.splice(0..1, tag.iter().copied());
+                }
+                self.last_byte = self.line.last().copied().or(self.last_byte);
+                n
+            } else {
+                let n = reader.read(out)?;
+                if n > 0 {
+                    self.last_byte = Some(out[n - 1]);
+                    return Ok(n);
+                }
+                n
+            };
+            if n == 0 {
+                // last line of an input may be not ended with newline
+                if self.last_byte.is_some_and(|b| b != b'\n') {
+                    self.line.push(b'\n');
+                }
+                self.last_byte = None;
+                self.current = None;
+            }
         }
-        info!("reading from stdin");
-        Ok(Box::new(BufReader::new(io::stdin())))
     }
 }
 
 pub fn file_writer<P>(
     file_out: Option<P>,
     compression_level: u32,
+    threads: usize,
+    output_compression: Option<CompressionFormat>,
 ) -> Result<Box<dyn Write + Send>, FakitError>
 where
     P: AsRef<Path> + Copy,
 {
     if let Some(file_name) = file_out {
-        let fp = File::create(file_name)?;
-        if file_name
-            .as_ref()
-            .extension()
-            .is_some_and(|ext| ext == "gz")
-        {
-            Ok(Box::new(BufWriter::with_capacity(
-                BUFF_SIZE,
-                flate2::write::GzEncoder::new(fp, flate2::Compression::new(compression_level)),
-            )))
-        } else if file_name
-            .as_ref()
-            .extension()
-            .is_some_and(|ext| ext == "bz2")
-        {
-            Ok(Box::new(BufWriter::with_capacity(
-                BUFF_SIZE,
-                bzip2::write::BzEncoder::new(fp, bzip2::Compression::new(compression_level)),
-            )))
-        } else if file_name
-            .as_ref()
-            .extension()
-            .is_some_and(|ext| ext == "xz")
-        {
+        let fp = File::create(file_name).map_err(|e| FakitError::FileIoError {
+            path: file_name.as_ref().to_path_buf(),
+            source: e,
+        })?;
+        let format = output_compression.unwrap_or_else(|| {
+            match file_name.as_ref().extension().and_then(|ext| ext.to_str()) {
+                Some("bgz") => CompressionFormat::Bgzf,
+                Some("gz") => CompressionFormat::Gzip,
+                Some("bz2") => CompressionFormat::Bzip2,
+                Some("xz") => CompressionFormat::Xz,
+                Some("zst") => CompressionFormat::Zstd,
+                _ => CompressionFormat::Plain,
+            }
+        });
+        compressed_writer(fp, format, compression_level, threads)
+    } else {
+        compressed_writer(
+            io::stdout(),
+            output_compression.unwrap_or(CompressionFormat::Plain),
+            compression_level,
+            threads,
+        )
+    }
+}
+
+fn compressed_writer<W>(
+    inner: W,
+    format: CompressionFormat,
+    compression_level: u32,
+    threads: usize,
+) -> Result<Box<dyn Write + Send>, FakitError>
+where
+    W: Write + Send + 'static,
+{
+    // zstd accepts level 1-22, the others 1-9
+    let level = match format {
+        CompressionFormat::Zstd | CompressionFormat::Plain => compression_level,
+        _ if compression_level > 9 => {
+            warn!(
+                "compression level {} is out of range 1-9 for {:?} output, use 9 instead",
+                compression_level, format
+            );
+            9
+        }
+        _ => compression_level,
+    };
+
+    match format {
+        // bgzf is valid gzip, blocks are compressed in parallel and can be indexed by faidx
+        CompressionFormat::Bgzf => Ok(Box::new(BufWriter::with_capacity(
+            BUFF_SIZE,
+            bgzf_writer(inner, level, threads)?,
+        ))),
+        CompressionFormat::Gzip if threads > 1 => Ok(Box::new(BufWriter::with_capacity(
+            BUFF_SIZE,
+            bgzf_writer(inner, level, threads)?,
+        ))),
+        CompressionFormat::Gzip => Ok(Box::new(BufWriter::with_capacity(
+            BUFF_SIZE,
+            flate2::write::GzEncoder::new(inner, flate2::Compression::new(level)),
+        ))),
+        CompressionFormat::Bzip2 => Ok(Box::new(BufWriter::with_capacity(
+            BUFF_SIZE,
+            bzip2::write::BzEncoder::new(inner, bzip2::Compression::new(level)),
+        ))),
+        CompressionFormat::Xz if threads > 1 => {
+            let stream = xz2::stream::MtStreamBuilder::new()
+                .threads(threads as u32)
+                .preset(level)
+                .check(xz2::stream::Check::Crc64)
+                .encoder()
+                .map_err(io::Error::from)?;
             Ok(Box::new(BufWriter::with_capacity(
                 BUFF_SIZE,
-                xz2::write::XzEncoder::new(fp, compression_level),
+                xz2::write::XzEncoder::new_stream(inner, stream),
             )))
-        } else if file_name
-            .as_ref()
-            .extension()
-            .is_some_and(|ext| ext == "zst")
-        {
-            let level = match compression_level {
-                1 => 1,
-                2 => 3,
-                3 => 7,
-                4 => 11,
-                _ => 3,
-            };
+        }
+        CompressionFormat::Xz => Ok(Box::new(BufWriter::with_capacity(
+            BUFF_SIZE,
+            xz2::write::XzEncoder::new(inner, level),
+        ))),
+        CompressionFormat::Zstd => {
+            let mut encoder = zstd::stream::write::Encoder::new(inner, level as i32)?;
+            if threads > 1 {
+                encoder.multithread(threads as u32)?;
+            }
             Ok(Box::new(BufWriter::with_capacity(
                 BUFF_SIZE,
-                zstd::stream::write::Encoder::new(fp, level)?,
+                encoder.auto_finish(),
             )))
-        } else {
-            Ok(Box::new(BufWriter::with_capacity(BUFF_SIZE, fp)))
         }
+        CompressionFormat::Plain => Ok(Box::new(BufWriter::with_capacity(BUFF_SIZE, inner))),
+    }
+}
+
+fn bgzf_writer<W>(
+    inner: W,
+    compression_level: u32,
+    threads: usize,
+) -> Result<Box<dyn Write + Send>, FakitError>
+where
+    W: Write + Send + 'static,
+{
+    let level = bgzf::io::writer::CompressionLevel::try_from(compression_level as u8)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if threads > 1 {
+        Ok(Box::new(
+            bgzf::io::multithreaded_writer::Builder::default()
+                .set_compression_level(level)
+                .set_worker_count(NonZeroUsize::new(threads).unwrap_or(NonZeroUsize::MIN))
+                .build_from_writer(inner),
+        ))
     } else {
-        Ok(Box::new(BufWriter::new(io::stdout())))
+        Ok(Box::new(
+            bgzf::io::writer::Builder::default()
+                .set_compression_level(level)
+                .build_from_writer(inner),
+        ))
+    }
+}
+
+/// Records of a filled batch, count is the number of records read before this batch and is advanced
+/// while iterating, so an invalid record is reported with its index in the input.
+pub fn batch_records<'a>(
+    rset: &'a RecordSet,
+    count: &'a mut usize,
+) -> impl Iterator<Item = Result<RefRecord<'a>, FakitError>> + 'a {
+    rset.iter().map(move |rec| {
+        *count += 1;
+        let index = *count;
+        rec.map_err(|source| FakitError::InvalidRecord { index, source })
+    })
+}
+
+/// Process fasta records with worker threads, batches filled by paraseq are handed out to workers
+/// and the output of each batch is written in input order. At most a few batches per thread are
+/// in flight, so a slow batch can't make finished batches pile up in memory. Returns the number of
+/// records that the callback reported as kept.
+pub fn process_records<R, W, F>(
+    mut reader: Reader<R>,
+    threads: usize,
+    writer: &mut W,
+    func: F,
+) -> Result<usize, FakitError>
+where
+    R: Read + Send,
+    W: Write + ?Sized,
+    F: Fn(&RefRecord, &mut Vec<u8>) -> Result<bool, FakitError> + Sync,
+{
+    // returns kept and seen records of the batch, record index in error is counted in the batch
+    let process_batch =
+        |rset: &RecordSet, buf: &mut Vec<u8>| -> Result<(usize, usize), FakitError> {
+            let (mut kept, mut seen) = (0usize, 0usize);
+            for rec in batch_records(rset, &mut seen) {
+                if func(&rec?, buf)? {
+                    kept += 1;
+                }
+            }
+            Ok((kept, seen))
+        };
+
+    let mut total = 0usize;
+    let mut nrec = 0usize;
+    if threads <= 1 {
+        let mut rset = RecordSet::default();
+        let mut buf = Vec::with_capacity(BUFF_SIZE);
+        while rset.fill(&mut reader)? {
+            let (kept, seen) =
+                process_batch(&rset, &mut buf).map_err(|e| e.with_record_offset(nrec))?;
+            total += kept;
+            nrec += seen;
+            writer.write_all(&buf)?;
+            buf.clear();
+        }
+        return Ok(total);
+    }
+
+    info!("process records with {} threads", threads);
+    thread::scope(|scope| -> Result<usize, FakitError> {
+        let (batch_tx, batch_rx) = mpsc::sync_channel::<(usize, RecordSet)>(threads * 2);
+        let batch_rx = Arc::new(Mutex::new(batch_rx));
+        let (free_tx, free_rx) = mpsc::channel::<RecordSet>();
+        let (out_tx, out_rx) =
+            mpsc::sync_channel::<(usize, Result<(Vec<u8>, usize, usize), FakitError>)>(threads * 2);
+        // a batch is read only with a free slot, slots are given back once a batch is written
+        let window = threads * 4;
+        let (slot_tx, slot_rx) = mpsc::sync_channel::<()>(window);
+        for _ in 0..window {
+            let _ = slot_tx.send(());
+        }
+
+        let reader_handle = scope.spawn(move || -> Result<(), FakitError> {
+            let mut idx = 0usize;
+            while slot_rx.recv().is_ok() {
+                let mut rset = free_rx.try_recv().unwrap_or_default();
+                if !rset.fill(&mut reader)? {
+                    break;
+                }
+                if batch_tx.send((idx, rset)).is_err() {
+                    break;
+                }
+                idx += 1;
+            }
+            Ok(())
+        });
+
+        for _ in 0..threads {
+            let batch_rx = Arc::clone(&batch_rx);
+            let free_tx = free_tx.clone();
+            let out_tx = out_tx.clone();
+            let process_batch = &process_batch;
+            scope.spawn(move || {
+                loop {
+                    let msg = batch_rx.lock().recv();
+                    let Ok((idx, rset)) = msg else {
+                        break;
+                    };
+                    let mut buf = Vec::new();
+                    let res = process_batch(&rset, &mut buf).map(|(kept, seen)| (buf, kept, seen));
+                    let _ = free_tx.send(rset);
+                    if out_tx.send((idx, res)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(out_tx);
+
+        // write batches back in input order
+        let mut pending = BTreeMap::new();
+        let mut next = 0usize;
+        for (idx, res) in out_rx {
+            pending.insert(idx, res);
+            while let Some(res) = pending.remove(&next) {
+                let (buf, kept, seen) = res.map_err(|e| e.with_record_offset(nrec))?;
+                writer.write_all(&buf)?;
+                total += kept;
+                nrec += seen;
+                next += 1;
+                let _ = slot_tx.send(());
+            }
+        }
+
+        match reader_handle.join() {
+            Ok(res) => res?,
+            Err(_) => return Err(FakitError::ThreadJoinError),
+        }
+        Ok(total)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use paraseq::fastx::Record;
+
+    const DATA: &[u8] = b">seq1\nACGT\n";
+
+    fn compressed(format: CompressionFormat) -> Vec<u8> {
+        let mut buf = vec![];
+        match format {
+            CompressionFormat::Bgzf => {
+                let mut w = bgzf::io::Writer::new(&mut buf);
+                w.write_all(DATA).unwrap();
+                w.finish().unwrap();
+            }
+            CompressionFormat::Gzip => {
+                let mut w = flate2::write::GzEncoder::new(&mut buf, flate2::Compression::fast());
+                w.write_all(DATA).unwrap();
+                w.finish().unwrap();
+            }
+            CompressionFormat::Bzip2 => {
+                let mut w = bzip2::write::BzEncoder::new(&mut buf, bzip2::Compression::fast());
+                w.write_all(DATA).unwrap();
+                w.finish().unwrap();
+            }
+            CompressionFormat::Xz => {
+                let mut w = xz2::write::XzEncoder::new(&mut buf, 1);
+                w.write_all(DATA).unwrap();
+                w.finish().unwrap();
+            }
+            CompressionFormat::Zstd => buf = zstd::encode_all(DATA, 1).unwrap(),
+            CompressionFormat::Plain => buf = DATA.to_vec(),
+        }
+        buf
+    }
+
+    #[test]
+    fn detect_compression_by_magic() {
+        for format in [
+            CompressionFormat::Bgzf,
+            CompressionFormat::Gzip,
+            CompressionFormat::Bzip2,
+            CompressionFormat::Xz,
+            CompressionFormat::Zstd,
+            CompressionFormat::Plain,
+        ] {
+            let buf = compressed(format);
+            assert_eq!(detect_magic(&buf[..buf.len().min(MAGIC_MAX_LEN)]), format);
+        }
+    }
+
+    #[test]
+    fn detect_short_input() {
+        assert_eq!(detect_magic(b""), CompressionFormat::Plain);
+        assert_eq!(detect_magic(&GZ_MAGIC), CompressionFormat::Gzip);
+        // FEXTRA flag without the BC subfield is plain gzip
+        assert_eq!(detect_magic(&BGZF_MAGIC), CompressionFormat::Gzip);
+        assert_eq!(detect_magic(&ZSTD_MAGIC[..3]), CompressionFormat::Plain);
+    }
+
+    // fasta records s0, s1, ... enough for several batches
+    fn numbered_records(n: usize) -> Vec<u8> {
+        (0..n)
+            .flat_map(|i| format!(">s{}\nACGT\n", i).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn process_records_keeps_order() {
+        let data = numbered_records(20_000);
+        let expect = (0..20_000)
+            .step_by(2)
+            .map(|i| format!("s{}\n", i))
+            .collect::<String>();
+        for threads in [1, 2, 8] {
+            let mut out = vec![];
+            let kept = process_records(Reader::new(&data[..]), threads, &mut out, |rec, buf| {
+                // odd records are dropped
+                if rec.id_str()[1..].parse::<usize>().unwrap() % 2 == 1 {
+                    return Ok(false);
+                }
+                writeln!(buf, "{}", rec.id_str())?;
+                Ok(true)
+            })
+            .unwrap();
+            assert_eq!(kept, 10_000);
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                expect,
+                "threads: {}",
+                threads
+            );
+        }
+    }
+
+    #[test]
+    fn process_records_worker_error() {
+        let data = numbered_records(20_000);
+        for threads in [1, 4] {
+            let mut out = vec![];
+            let res = process_records(Reader::new(&data[..]), threads, &mut out, |rec, buf| {
+                if rec.id_str() == "s15000" {
+                    return Err(FakitError::InvalidArgument("bad record".to_string()));
+                }
+                writeln!(buf, "{}", rec.id_str())?;
+                Ok(true)
+            });
+            assert!(matches!(res, Err(FakitError::InvalidArgument(x)) if x == "bad record"));
+            // only whole batches before the failed one are written, in input order
+            let out = String::from_utf8(out).unwrap();
+            let ids = out.lines().collect::<Vec<_>>();
+            assert!(ids.len() < 15_000);
+            assert!(
+                ids.iter()
+                    .enumerate()
+                    .all(|(i, id)| *id == format!("s{}", i))
+            );
+        }
     }
 }